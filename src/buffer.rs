@@ -0,0 +1,242 @@
+use super::encoding::EncodingWriter;
+use super::line::Line;
+use super::options::{FileFormat, Options};
+use encoding_rs::{UTF_16BE, UTF_16LE, UTF_8};
+use ropey::{iter::Chunks, Rope};
+use std::io::{self, Write};
+use std::ops::Range;
+
+//The text being edited, stored in a rope so inserting, removing and
+//indexing lines is O(log n) even for huge files. Every line, the last
+//one included, is stored followed by a '\n', so an empty rope is a buffer
+//without lines
+#[derive(Debug, Clone, Default)]
+pub struct Buffer {
+    text: Rope,
+    options: Options,
+}
+
+impl Buffer {
+    pub fn new() -> Self {
+        Buffer::default()
+    }
+
+    //Takes the text of a file, detecting its byte order mark, line endings
+    //and whether the last line ends with one, so it can be written back as it was
+    pub fn with_options(s: &str, mut options: Options) -> Self {
+        options.bomb = s.starts_with('\u{feff}');
+        let s = s.strip_prefix('\u{feff}').unwrap_or(s);
+        options.endofline = s.is_empty() || s.ends_with('\n');
+        let n_lf = s.matches('\n').count();
+        options.fileformat = if n_lf > 0 && s.matches("\r\n").count() == n_lf {
+            FileFormat::Dos
+        } else {
+            FileFormat::Unix
+        };
+        let mut text = match options.fileformat {
+            FileFormat::Unix => Rope::from_str(s),
+            FileFormat::Dos => Rope::from_str(&s.replace("\r\n", "\n")),
+        };
+        if !s.is_empty() && !s.ends_with('\n') {
+            text.insert_char(text.len_chars(), '\n');
+        }
+        Buffer { text, options }
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    pub fn options_mut(&mut self) -> &mut Options {
+        &mut self.options
+    }
+
+    //Number of lines
+    pub fn len(&self) -> usize {
+        self.text.len_lines() - 1
+    }
+
+    //Number of characters, counting a line break after every line
+    pub fn len_chars(&self) -> usize {
+        self.text.len_chars()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn line(&self, y: usize) -> Line {
+        assert!(
+            y < self.len(),
+            "Buffer: Tried to get an invalid line({})",
+            y
+        );
+        let start = self.text.line_to_char(y);
+        let end = self.text.line_to_char(y + 1) - 1;
+        Line::with_tab_size(
+            &self.text.slice(start..end).to_string(),
+            self.options.tabstop,
+        )
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = Line> + '_ {
+        (0..self.len()).map(move |y| self.line(y))
+    }
+
+    pub fn push(&mut self, line: Line) {
+        self.splice(self.len()..self.len(), vec![line]);
+    }
+
+    //Replaces the lines in range with lines
+    pub fn splice(&mut self, range: Range<usize>, lines: impl IntoIterator<Item = Line>) {
+        assert!(
+            range.start <= range.end && range.end <= self.len(),
+            "Buffer: Tried to replace an invalid range({:?})",
+            range
+        );
+        let start = self.text.line_to_char(range.start);
+        let end = self.text.line_to_char(range.end);
+        self.text.remove(start..end);
+        let mut text = String::new();
+        for line in lines {
+            text.push_str(&line.get_content());
+            text.push('\n');
+        }
+        self.text.insert(start, &text);
+    }
+
+    //The whole text, as pieces of '\n' terminated lines
+    pub fn chunks(&self) -> Chunks<'_> {
+        self.text.chunks()
+    }
+
+    //Writes the text as it must be stored in the file, honouring fileformat,
+    //fileencoding, bomb and endofline
+    pub fn write_to<W: Write>(&self, writer: W) -> io::Result<W> {
+        let encoding = self.options.fileencoding;
+        let mut writer = EncodingWriter::new(writer, encoding);
+        //Only Unicode encodings have a byte order mark
+        if self.options.bomb && [UTF_8, UTF_16LE, UTF_16BE].contains(&encoding) {
+            writer.write_str("\u{feff}")?;
+        }
+        let mut chunks = self.text.chunks().peekable();
+        while let Some(mut chunk) = chunks.next() {
+            if chunks.peek().is_none() && !self.options.endofline {
+                chunk = chunk.strip_suffix('\n').unwrap_or(chunk);
+            }
+            match self.options.fileformat {
+                FileFormat::Unix => writer.write_str(chunk)?,
+                format => writer.write_str(&chunk.replace('\n', format.line_ending()))?,
+            }
+        }
+        writer.finish()
+    }
+}
+
+impl PartialEq<Vec<Line>> for Buffer {
+    fn eq(&self, other: &Vec<Line>) -> bool {
+        self.len() == other.len() && self.lines().zip(other.iter()).all(|(a, b)| a == *b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_text(s: &str) -> Buffer {
+        Buffer::with_options(s, Options::default())
+    }
+
+    #[test]
+    fn split_lines() {
+        let buffer = from_text("á\tb\n\nc");
+        assert_eq!(
+            buffer,
+            vec![Line::new("á\tb"), Line::new(""), Line::new("c")]
+        );
+    }
+
+    #[test]
+    fn from_text_trailing_newline() {
+        assert_eq!(from_text("a\n").len(), 1);
+        assert_eq!(from_text("").len(), 0);
+    }
+
+    #[test]
+    fn carriage_return_is_not_a_line_break() {
+        let buffer = from_text("a\rb\n");
+        assert_eq!(buffer.line(0).get_content(), "a\rb");
+    }
+
+    #[test]
+    fn splice() {
+        let mut buffer = from_text("a\nb\nc\n");
+        buffer.splice(1..2, vec![Line::new("x"), Line::new("y")]);
+        assert_eq!(
+            buffer,
+            vec![
+                Line::new("a"),
+                Line::new("x"),
+                Line::new("y"),
+                Line::new("c")
+            ]
+        );
+        buffer.splice(0..4, Vec::new());
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn lines_use_tabstop() {
+        let mut buffer = from_text("\ta\n");
+        buffer.options_mut().tabstop = 2;
+        assert_eq!(buffer.line(0).len(), 3);
+    }
+
+    fn written(buffer: &Buffer) -> String {
+        String::from_utf8(buffer.write_to(Vec::new()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn keeps_file_format() {
+        for text in &["a\r\nb\r\n", "a\nb", "\u{feff}a\r\nb", "a\r\nb\n", "", "\n"] {
+            assert_eq!(written(&from_text(text)), *text);
+        }
+    }
+
+    #[test]
+    fn detects_file_format() {
+        let buffer = from_text("\u{feff}a\r\nb");
+        assert_eq!(buffer, vec![Line::new("a"), Line::new("b")]);
+        assert_eq!(buffer.options().fileformat, FileFormat::Dos);
+        assert!(buffer.options().bomb);
+        assert!(!buffer.options().endofline);
+    }
+
+    #[test]
+    fn mixed_endings_are_unix() {
+        let buffer = from_text("a\r\nb\n");
+        assert_eq!(buffer.options().fileformat, FileFormat::Unix);
+        assert_eq!(buffer.line(0).get_content(), "a\r");
+    }
+
+    #[test]
+    fn convert_to_dos() {
+        let mut buffer = from_text("a\nb\n");
+        buffer.options_mut().fileformat = FileFormat::Dos;
+        assert_eq!(written(&buffer), "a\r\nb\r\n");
+    }
+
+    #[test]
+    fn write_latin1() {
+        let mut buffer = from_text("\u{feff}año\n");
+        buffer.options_mut().fileencoding = encoding_rs::WINDOWS_1252;
+        assert_eq!(buffer.write_to(Vec::new()).unwrap(), b"a\xf1o\n");
+    }
+
+    #[test]
+    #[should_panic]
+    fn line_beyond_len() {
+        let buffer = from_text("a\n");
+        buffer.line(1);
+    }
+}
@@ -0,0 +1,213 @@
+use super::registers::Register;
+use crossterm::{execute, style::Print};
+use std::cell::OnceCell;
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0, |n, (i, &byte)| n | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(BASE64[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+//The escape sequence that makes the terminal copy text to the clipboard, or
+//to the primary selection
+fn osc52(text: &str, primary: bool) -> String {
+    let selection = if primary { 'p' } else { 'c' };
+    format!("\x1b]52;{};{}\x07", selection, base64(text.as_bytes()))
+}
+
+fn in_path(program: &str) -> bool {
+    env::var_os("PATH")
+        .is_some_and(|paths| env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+}
+
+//Programs that copy to and paste from the clipboard
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Tool {
+    WlClipboard,
+    Xclip,
+    Pbcopy,
+}
+
+impl Tool {
+    fn find() -> Option<Tool> {
+        if env::var_os("WAYLAND_DISPLAY").is_some() && in_path("wl-copy") {
+            Some(Tool::WlClipboard)
+        } else if env::var_os("DISPLAY").is_some() && in_path("xclip") {
+            Some(Tool::Xclip)
+        } else if in_path("pbcopy") {
+            Some(Tool::Pbcopy)
+        } else {
+            None
+        }
+    }
+
+    fn command(self, paste: bool, primary: bool) -> Command {
+        let mut command = match (self, paste) {
+            (Tool::WlClipboard, false) => Command::new("wl-copy"),
+            (Tool::WlClipboard, true) => {
+                let mut command = Command::new("wl-paste");
+                command.arg("--no-newline");
+                command
+            }
+            (Tool::Xclip, _) => {
+                let mut command = Command::new("xclip");
+                let selection = if primary { "primary" } else { "clipboard" };
+                command.args(["-selection", selection]);
+                if paste {
+                    command.arg("-o");
+                }
+                command
+            }
+            (Tool::Pbcopy, false) => Command::new("pbcopy"),
+            (Tool::Pbcopy, true) => Command::new("pbpaste"),
+        };
+        if self == Tool::WlClipboard && primary {
+            command.arg("--primary");
+        }
+        command.stderr(Stdio::null());
+        command
+    }
+
+    fn copy(self, text: &str, primary: bool) -> io::Result<()> {
+        let mut child = self
+            .command(false, primary)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        match child.wait()?.success() {
+            true => Ok(()),
+            false => Err(io::Error::other("copy failed")),
+        }
+    }
+
+    fn paste(self, primary: bool) -> Option<String> {
+        let output = self.command(true, primary).output().ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        Some(String::from_utf8_lossy(&output.stdout).replace("\r\n", "\n"))
+    }
+}
+
+//The + and * registers, which are the clipboard and the primary selection.
+//They go through wl-copy, xclip or pbcopy if there is one, or else through
+//OSC 52, which the terminal handles even over SSH. The terminal can't be
+//asked for the text, so the last one copied is kept to paste it. The tool
+//is looked for the first time one of the registers is used
+#[derive(Default)]
+pub struct Clipboard {
+    tool: OnceCell<Option<Tool>>,
+    //Whether OSC 52 can be written, as it only makes sense to a terminal
+    osc52: OnceCell<bool>,
+    //Clipboard and primary selection
+    copied: [Register; 2],
+}
+
+//The text of a register as other programs see it, linewise ending in '\n'
+fn plain_text(register: &Register) -> String {
+    match register.linewise {
+        true => format!("{}\n", register.text),
+        false => register.text.clone(),
+    }
+}
+
+impl Clipboard {
+    fn tool(&self) -> Option<Tool> {
+        *self.tool.get_or_init(Tool::find)
+    }
+
+    pub fn copy(&mut self, register: &Register, primary: bool) {
+        self.copied[primary as usize] = register.clone();
+        let text = plain_text(register);
+        let copied = self
+            .tool()
+            .is_some_and(|tool| tool.copy(&text, primary).is_ok());
+        if !copied && *self.osc52.get_or_init(|| io::stdout().is_terminal()) {
+            let _ = execute!(io::stdout(), Print(osc52(&text, primary)));
+        }
+    }
+
+    //Text ending in a line break is pasted as lines, unless it was copied
+    //from here as text
+    pub fn paste(&self, primary: bool) -> Register {
+        let copied = &self.copied[primary as usize];
+        let text = match self.tool().and_then(|tool| tool.paste(primary)) {
+            Some(text) if text != plain_text(copied) => text,
+            _ => return copied.clone(),
+        };
+        match text.strip_suffix('\n') {
+            Some(lines) => Register {
+                text: lines.to_owned(),
+                linewise: true,
+            },
+            None => Register {
+                text,
+                linewise: false,
+            },
+        }
+    }
+
+    //The last text copied from here, without asking the tool
+    pub fn copied(&self, primary: bool) -> &Register {
+        &self.copied[primary as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(osc52("ñ\n", false), "\x1b]52;c;w7EK\x07");
+    }
+
+    #[test]
+    fn without_tool() {
+        let mut clipboard = Clipboard {
+            tool: OnceCell::from(None),
+            osc52: OnceCell::from(false),
+            copied: Default::default(),
+        };
+        let register = Register {
+            text: String::from("a"),
+            linewise: true,
+        };
+        clipboard.copy(&register, true);
+        assert_eq!(clipboard.paste(true), register);
+        assert_eq!(clipboard.paste(false), Register::default());
+    }
+
+    #[test]
+    fn tool_found_on_first_use() {
+        let clipboard = Clipboard::default();
+        assert_eq!(clipboard.copied(false), &Register::default());
+        assert!(clipboard.tool.get().is_none());
+        clipboard.paste(false);
+        assert!(clipboard.tool.get().is_some());
+    }
+}
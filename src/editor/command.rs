@@ -1,61 +1,332 @@
-use super::{Editor, Mode};
+use super::history::UndoStep;
+use super::motion::{Motion, MotionRange, RangeKind};
+use super::{Cursor, Editor, Mode, Operator};
 use crossterm::{
-    event::{Event, KeyCode, KeyEvent},
+    event::{Event, KeyCode, KeyEvent, KeyModifiers},
     execute,
     terminal::{disable_raw_mode, LeaveAlternateScreen},
     Result,
 };
+use encoding_rs::Encoding;
+use std::cmp::{max, min};
 use std::io;
 use std::io::prelude::*;
 use std::process::exit;
 
 impl Editor {
     pub(super) fn match_event_command(&mut self, event: Event) -> Result<()> {
+        if self.inserting_register {
+            self.inserting_register = false;
+            if let Event::Key(KeyEvent {
+                code: KeyCode::Char(name),
+                ..
+            }) = event
+            {
+                //The command line has a single line, so line breaks are
+                //typed as spaces
+                if let Some(register) = self.register(name) {
+                    self.command_buffer
+                        .push_str(&register.text.replace('\n', " "));
+                }
+            }
+            return Ok(());
+        }
         match event {
+            Event::Key(KeyEvent {
+                code: KeyCode::Char('r'),
+                modifiers: KeyModifiers::CONTROL,
+            }) => self.inserting_register = true,
             Event::Key(KeyEvent {
                 code: KeyCode::Char(key),
                 ..
             }) => {
                 self.command_buffer.push(key);
+                self.preview_search();
             }
             Event::Key(KeyEvent {
                 code: KeyCode::Enter,
                 ..
             }) => self.execute_command()?,
+            //Erasing the : or / the command line starts with closes it
             Event::Key(KeyEvent {
                 code: KeyCode::Backspace,
                 ..
-            }) => {
+            }) if self.command_buffer.chars().count() > 1 => {
                 self.command_buffer.pop();
+                self.preview_search();
             }
+            Event::Key(KeyEvent {
+                code: KeyCode::Esc, ..
+            })
+            | Event::Key(KeyEvent {
+                code: KeyCode::Backspace,
+                ..
+            }) => {
+                if self.typed_search().is_some() {
+                    self.cancel_search();
+                }
+                self.mode = Mode::Normal;
+            }
+            Event::Key(KeyEvent {
+                code: code @ KeyCode::Up,
+                ..
+            })
+            | Event::Key(KeyEvent {
+                code: code @ KeyCode::Down,
+                ..
+            }) if self.typed_search().is_some() => self.browse_search_history(code == KeyCode::Up),
             _ => {}
         }
         Ok(())
     }
 
     fn execute_command(&mut self) -> Result<()> {
-        match self.command_buffer.as_ref() {
+        let command = self.command_buffer.clone();
+        let search = self.typed_search();
+        self.mode = Mode::Normal;
+        if let Some(search) = search {
+            self.run_search(search);
+            return Ok(());
+        }
+        if command.len() > 1 {
+            self.registers.last_command = command[1..].to_owned();
+        }
+        self.run_command(&command)
+    }
+
+    pub(super) fn run_command(&mut self, command: &str) -> Result<()> {
+        if self.run_substitute(command) {
+            return Ok(());
+        }
+        if let Some(command) = command.strip_prefix(":'<,'>") {
+            self.run_selection_command(command);
+            return Ok(());
+        }
+        let (name, arg) = command.split_at(command.find(' ').unwrap_or(command.len()));
+        let arg = arg.trim();
+        match name {
             ":q" => {
+                self.remove_swap_file();
                 execute!(io::stdout(), LeaveAlternateScreen)?;
                 //exit won't call destructors
                 disable_raw_mode()?;
                 exit(0);
             }
-            ":w" => {
-                self.mode = Mode::Normal;
-                self.save_to_file()
+            ":w" | ":w!" => {
+                self.write_file(name.ends_with('!'), arg);
+                Ok(())
             }
-            ":wq" => {
-                self.save_to_file()?;
+            ":wq" | ":wq!" => {
+                if !self.write_file(name.ends_with('!'), arg) {
+                    return Ok(());
+                }
+                self.remove_swap_file();
                 execute!(io::stdout(), LeaveAlternateScreen)?;
                 //exit won't call destructors
                 disable_raw_mode()?;
                 exit(0);
             }
+            ":undolist" | ":undol" => {
+                self.status_message = self.undo_list();
+                Ok(())
+            }
+            ":earlier" | ":later" => {
+                match UndoStep::parse(arg) {
+                    Some(step) if name == ":earlier" => self.earlier(step),
+                    Some(step) => self.later(step),
+                    None => self.status_message = format!("Invalid argument: {}", arg),
+                }
+                Ok(())
+            }
+            ":e" | ":edit" | ":e!" | ":edit!" => {
+                self.edit(arg, name.ends_with('!'));
+                Ok(())
+            }
+            ":registers" | ":reg" | ":display" | ":di" => {
+                self.status_message = self.register_list();
+                Ok(())
+            }
+            ":nohlsearch" | ":noh" => {
+                self.highlight_search = false;
+                Ok(())
+            }
+            ":set" | ":se" => {
+                self.set_options(arg);
+                Ok(())
+            }
+            ":$" => {
+                self.move_cursor_to(Motion::LastLine, 1);
+                Ok(())
+            }
+            //:N jumps to line N
+            name if name.len() > 1 && name[1..].bytes().all(|b| b.is_ascii_digit()) => {
+                let n = name[1..].parse().unwrap_or(usize::MAX);
+                self.move_cursor_to(Motion::Line(n), 1);
+                Ok(())
+            }
             _ => {
-                self.mode = Mode::Normal;
+                self.status_message = format!("Not an editor command: {}", command);
                 Ok(())
             }
         }
     }
+
+    //Commands typed after : in Visual mode, which act on the lines of the
+    //last selection
+    fn run_selection_command(&mut self, command: &str) {
+        let (first, last) = match self.visual_lines {
+            Some(lines) => lines,
+            None => {
+                self.status_message = String::from("Mark not set");
+                return;
+            }
+        };
+        let last_y = self.buffer.len() - 1;
+        let (first, last) = (min(first, last_y), min(last, last_y));
+        let operator = match command.trim() {
+            "" => {
+                self.move_cursor_to(Motion::Line(last + 1), 1);
+                return;
+            }
+            "j" | "join" => {
+                self.join_lines(first, max(last, min(first + 1, last_y)));
+                return;
+            }
+            "d" | "delete" => Operator::Delete,
+            "y" | "yank" => Operator::Yank,
+            ">" => Operator::ShiftRight,
+            "<" => Operator::ShiftLeft,
+            _ => {
+                self.status_message = format!("Not an editor command: {}", command);
+                return;
+            }
+        };
+        let range = MotionRange {
+            start: Cursor { x: 0, y: first },
+            end: Cursor { x: 0, y: last },
+            kind: RangeKind::Linewise,
+        };
+        self.apply_operator(operator, range);
+    }
+
+    //Reloads the current file, or opens another one. "++enc=name" forces
+    //the encoding the file is read with
+    fn edit(&mut self, args: &str, force: bool) {
+        let mut encoding = None;
+        let mut path = self.file_name.clone();
+        for arg in args.split_whitespace() {
+            match arg
+                .strip_prefix("++enc=")
+                .or_else(|| arg.strip_prefix("++encoding="))
+            {
+                Some(label) => match Encoding::for_label(label.as_bytes()) {
+                    Some(label_encoding) => encoding = Some(label_encoding),
+                    None => {
+                        self.status_message = format!("Invalid encoding: {}", label);
+                        return;
+                    }
+                },
+                None => path = arg.to_owned(),
+            }
+        }
+        if !force && self.history.is_modified() {
+            self.status_message = String::from("No write since last change (add ! to override)");
+            return;
+        }
+        if path != self.file_name {
+            self.cursor = Cursor::new();
+            self.y_scroll = 0;
+        }
+        self.remove_swap_file();
+        if let Err(err) = self.load_to_buf_as(&path, encoding) {
+            self.status_message = format!("Can't open \"{}\": {}", path, err);
+        }
+    }
+
+    fn set_options(&mut self, args: &str) {
+        let args = if args.is_empty() { "all" } else { args };
+        let mut messages = Vec::new();
+        for arg in args.split_whitespace() {
+            match self.buffer.options_mut().set(arg) {
+                Ok(Some(message)) | Err(message) => messages.push(message),
+                Ok(None) => {}
+            }
+        }
+        self.status_message = messages.join(" ");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Line;
+    use super::*;
+
+    #[test]
+    fn set_tabstop() -> Result<()> {
+        let mut editor = Editor::new();
+        editor.buffer.push(Line::new("\ta"));
+        editor.run_command(":set ts=8 et")?;
+        assert_eq!(editor.buffer.line(0).len(), 9);
+        assert!(editor.buffer.options().expandtab);
+        editor.run_command(":se ts?")?;
+        assert_eq!(editor.status_message, "tabstop=8");
+        Ok(())
+    }
+
+    #[test]
+    fn edit_with_changes() -> Result<()> {
+        let mut editor = Editor::new();
+        editor.buffer.push(Line::new("a"));
+        editor.set_line(0, Line::new("b"));
+        editor.commit_change();
+        editor.run_command(":e ++enc=latin1")?;
+        assert_eq!(
+            editor.status_message,
+            "No write since last change (add ! to override)"
+        );
+        editor.run_command(":e ++enc=foo")?;
+        assert_eq!(editor.status_message, "Invalid encoding: foo");
+        Ok(())
+    }
+
+    #[test]
+    fn goto_line() -> Result<()> {
+        let mut editor = Editor::new();
+        for line in &["a", " b", "c"] {
+            editor.buffer.push(Line::new(line));
+        }
+        editor.run_command(":2")?;
+        assert_eq!(editor.cursor, Cursor { x: 1, y: 1 });
+        editor.run_command(":99999999999999999999999")?;
+        assert_eq!(editor.cursor.y, 2);
+        editor.run_command(":0")?;
+        assert_eq!(editor.cursor.y, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn selection_commands() -> Result<()> {
+        let mut editor = Editor::new();
+        for line in ["a", "b", "c", "d"] {
+            editor.buffer.push(Line::new(line));
+        }
+        editor.run_command(":'<,'>d")?;
+        assert_eq!(editor.status_message, "Mark not set");
+        editor.visual_lines = Some((1, 2));
+        editor.run_command(":'<,'>d")?;
+        assert_eq!(editor.buffer, vec![Line::new("a"), Line::new("d")]);
+        assert_eq!(editor.register('"').unwrap().text, "b\nc");
+        editor.visual_lines = Some((0, 0));
+        editor.run_command(":'<,'>j")?;
+        assert_eq!(editor.buffer, vec![Line::new("a d")]);
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_command() -> Result<()> {
+        let mut editor = Editor::new();
+        editor.run_command(":foo")?;
+        assert_eq!(editor.status_message, "Not an editor command: :foo");
+        Ok(())
+    }
 }
@@ -0,0 +1,45 @@
+use super::Editor;
+use crossterm::Result;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+fn config_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".tinaterc"))
+}
+
+impl Editor {
+    //Runs every line of ~/.tinaterc as a command, like "set ts=8 et".
+    //Empty lines and lines starting with '"' are ignored
+    pub(super) fn read_config(&mut self) -> Result<()> {
+        match config_path().map(fs::read_to_string) {
+            Some(Ok(config)) => self.run_config(&config),
+            _ => Ok(()),
+        }
+    }
+
+    fn run_config(&mut self, config: &str) -> Result<()> {
+        for line in config.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('"') {
+                continue;
+            }
+            self.run_command(&format!(":{}", line.trim_start_matches(':')))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_config() -> Result<()> {
+        let mut editor = Editor::new();
+        editor.run_config("\" comment\n\nset ts=2\n:set et sw=3\n")?;
+        assert_eq!(editor.buffer.options().tabstop, 2);
+        assert_eq!(editor.buffer.options().shiftwidth(), 3);
+        assert!(editor.buffer.options().expandtab);
+        Ok(())
+    }
+}
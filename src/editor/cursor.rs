@@ -1,5 +1,5 @@
 use super::Editor;
-use std::cmp::min;
+use std::cmp::{max, min};
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Cursor {
@@ -35,7 +35,7 @@ impl Editor {
             y
         };
 
-        let len = self.buffer[y].len() + if tight { 0 } else { 1 };
+        let len = self.buffer.line(y).len() + if tight { 0 } else { 1 };
         if x >= len {
             if len == 0 {
                 (0, y)
@@ -49,11 +49,11 @@ impl Editor {
 
     pub(super) fn move_cursor_right(&mut self, n: usize, tight: bool) {
         for _ in 0..n {
-            match self.buffer[self.y()].next_valid_index(self.x(false)) {
+            match self.buffer.line(self.y()).next_valid_index(self.x(false)) {
                 Some(index) => self.cursor.x = index,
                 None => {
                     if !tight {
-                        self.cursor.x = self.buffer[self.y()].len()
+                        self.cursor.x = self.buffer.line(self.y()).len()
                     };
                     return;
                 }
@@ -63,7 +63,7 @@ impl Editor {
 
     pub(super) fn move_cursor_left(&mut self, n: usize, tight: bool) {
         for _ in 0..n {
-            self.cursor.x = match self.buffer[self.y()].prev_valid_index(self.x(tight)) {
+            self.cursor.x = match self.buffer.line(self.y()).prev_valid_index(self.x(tight)) {
                 Some(index) => index,
                 None => return,
             }
@@ -91,50 +91,98 @@ impl Editor {
     }
 
     fn assert_valid_pos(&self, mut pos: (usize, usize)) -> (usize, usize) {
-        if pos.0 != self.buffer[pos.1].len() && !self.buffer[pos.1].is_valid_index(pos.0) {
-            pos.0 = self.buffer[pos.1].prev_valid_index(pos.0).unwrap_or(0);
+        if pos.0 != self.buffer.line(pos.1).len() && !self.buffer.line(pos.1).is_valid_index(pos.0)
+        {
+            pos.0 = self.buffer.line(pos.1).prev_valid_index(pos.0).unwrap_or(0);
         }
         pos
     }
 
     pub(super) fn cursor_pos_to_screen_pos(&self, n_cols: u16, tight: bool) -> (u16, u16) {
         let (cursor_x, cursor_y) = self.pos(tight);
-        let x = (cursor_x % n_cols as usize) as u16;
-        let mut y = self.buffer[self.y_scroll..cursor_y]
-            .iter()
-            .fold(0, |acc, line| {
-                acc + 1 + ((line.len() - min(1, line.len())) / n_cols as usize) as u16
-            });
-        y += (cursor_x / n_cols as usize) as u16;
-
-        (x, y)
+        let (x, y) = self
+            .buffer
+            .line(cursor_y)
+            .screen_pos(cursor_x, n_cols as usize);
+        let y = (self.y_scroll..cursor_y)
+            .map(|y| self.buffer.line(y).row_starts(n_cols as usize).len())
+            .sum::<usize>()
+            + y;
+        //Rows are only clamped here, where the terminal needs them as u16
+        let clamp = |n: usize| min(n, u16::MAX as usize) as u16;
+        (clamp(x), clamp(y))
     }
 
+    //Scrolls as little as needed to show the cursor above the status bar.
+    //The first line shown is found going back from the cursor line, adding
+    //up their rows until the screen is full
     pub(super) fn recalculate_scroll(&mut self, term_size: (u16, u16)) {
-        //in this case the tight value doesn't matter because we only care about the y_scroll
+        let n_cols = term_size.0 as usize;
+        let n_rows = (term_size.1 as usize).saturating_sub(1);
         let (cursor_x, cursor_y) = self.pos(false);
-        loop {
-            if self.y_scroll > self.y() {
-                self.y_scroll -= 1;
-                continue;
+        self.y_scroll = min(self.y_scroll, cursor_y);
+        let (_, mut rows) = self.buffer.line(cursor_y).screen_pos(cursor_x, n_cols);
+        let mut first = cursor_y;
+        while first > self.y_scroll {
+            let line_rows = self.buffer.line(first - 1).row_starts(n_cols).len();
+            if rows + line_rows >= n_rows {
+                break;
             }
-            let mut y = self.buffer[self.y_scroll..cursor_y]
-                .iter()
-                .fold(0, |acc, line| {
-                    acc + 1 + ((line.len() - min(1, line.len())) / term_size.0 as usize) as u16
-                });
-            y += (cursor_x / term_size.0 as usize) as u16;
-            if y >= term_size.1 - 1 {
-                self.y_scroll += 1;
-                continue;
+            rows += line_rows;
+            first -= 1;
+        }
+        self.y_scroll = first;
+    }
+
+    //Number of lines from y_scroll that fit entirely in the screen, at least 1
+    pub(super) fn screen_lines(&self) -> usize {
+        let (n_cols, n_rows) = (self.screen_size.0 as usize, self.screen_size.1 as usize);
+        //The last row is the status bar
+        let n_rows = n_rows.saturating_sub(1);
+        let mut rows = 0;
+        let mut n = 0;
+        for y in self.y_scroll..self.buffer.len() {
+            rows += self.buffer.line(y).row_starts(n_cols).len();
+            if rows > n_rows {
+                break;
             }
-            break;
+            n += 1;
         }
+        max(n, 1)
+    }
+
+    //Ctrl-D and Ctrl-U, scroll the screen and the cursor count lines, half a
+    //screen by default
+    pub(super) fn scroll_half_page(&mut self, down: bool, count: Option<usize>) {
+        let n = count.unwrap_or(self.screen_lines() / 2).max(1);
+        let last_y = self.buffer.len() - 1;
+        if down {
+            self.y_scroll = min(self.y_scroll + n, last_y);
+            self.cursor.y = min(self.y() + n, last_y);
+        } else {
+            self.y_scroll = self.y_scroll.saturating_sub(n);
+            self.cursor.y = self.y().saturating_sub(n);
+        }
+        self.cursor.x = self.buffer.line(self.cursor.y).indent();
+    }
+
+    //Ctrl-F and Ctrl-B, scroll count screens keeping two lines of context
+    pub(super) fn scroll_pages(&mut self, forward: bool, count: usize) {
+        let n = max(self.screen_lines().saturating_sub(2), 1) * count;
+        if forward {
+            self.y_scroll = min(self.y_scroll + n, self.buffer.len() - 1);
+            self.cursor.y = max(self.y(), self.y_scroll);
+        } else {
+            self.y_scroll = self.y_scroll.saturating_sub(n);
+            self.cursor.y = min(self.y(), self.y_scroll + self.screen_lines() - 1);
+        }
+        self.cursor.x = self.buffer.line(self.cursor.y).indent();
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::super::test_helpers::{editor, type_keys};
     use super::super::*;
 
     #[test]
@@ -254,6 +302,17 @@ mod tests {
         assert_eq!(editor.cursor_pos_to_screen_pos(4, true), (3, 1));
     }
 
+    #[test]
+    fn screen_coords_wide() {
+        let mut editor = Editor::new();
+        editor.buffer.push(Line::new("中文字"));
+        editor.buffer.push(Line::new("a"));
+        editor.cursor.x = 4;
+        assert_eq!(editor.cursor_pos_to_screen_pos(5, true), (0, 1));
+        editor.cursor.y = 1;
+        assert_eq!(editor.cursor_pos_to_screen_pos(5, true), (0, 2));
+    }
+
     #[test]
     fn recalculate_scroll() {
         let mut editor = Editor::new();
@@ -264,4 +323,35 @@ mod tests {
         editor.recalculate_scroll((4, 2)); //2 to let the row for the status bar
         assert_eq!(editor.y_scroll, 1);
     }
+
+    #[test]
+    fn scroll_to_last_of_many_lines() {
+        let mut editor = editor(&["a"; 70000]);
+        type_keys(&mut editor, "G");
+        editor.recalculate_scroll((10, 6));
+        assert_eq!(editor.y_scroll, 69995);
+        assert_eq!(editor.cursor_pos_to_screen_pos(10, true), (0, 4));
+        type_keys(&mut editor, "gg");
+        editor.recalculate_scroll((10, 6));
+        assert_eq!(editor.y_scroll, 0);
+    }
+
+    #[test]
+    fn scroll_pages() {
+        let mut editor = Editor::new();
+        for _ in 0..20 {
+            editor.buffer.push(Line::new("a"));
+        }
+        editor.screen_size = (10, 6);
+        assert_eq!(editor.screen_lines(), 5);
+        editor.scroll_pages(true, 2);
+        assert_eq!((editor.y_scroll, editor.cursor.y), (6, 6));
+        editor.scroll_half_page(true, None);
+        assert_eq!((editor.y_scroll, editor.cursor.y), (8, 8));
+        editor.cursor.y = 12;
+        editor.scroll_pages(false, 1);
+        assert_eq!((editor.y_scroll, editor.cursor.y), (5, 9));
+        editor.scroll_half_page(false, Some(10));
+        assert_eq!((editor.y_scroll, editor.cursor.y), (0, 0));
+    }
 }
@@ -1,15 +1,210 @@
-use super::{Editor, Mode};
-use crossterm::{event::read, Result};
+use super::swap::{UPDATECOUNT, UPDATETIME};
+use super::{Editor, Mode, Operator};
+use crossterm::{
+    event::{poll, read, Event, KeyCode, KeyEvent, KeyModifiers},
+    Result,
+};
+use std::time::Duration;
 
 impl Editor {
     pub(super) fn process_event(&mut self) -> Result<()> {
-        let event = read()?;
+        let event = match self.next_queued_key() {
+            Some(key) => {
+                if self.interrupted()? {
+                    return Ok(());
+                }
+                Event::Key(key)
+            }
+            None => match self.typed_ahead.pop_front() {
+                Some(event) => event,
+                None => {
+                    if !poll(UPDATETIME)? {
+                        self.update_swap_file();
+                        return Ok(());
+                    }
+                    let event = read()?;
+                    self.record(&event);
+                    event
+                }
+            },
+        };
+        self.handle_event(event)
+    }
+
+    //Reads what was typed while queued keys are handled. Ctrl-C or Esc stop
+    //them, anything else is handled after them
+    fn interrupted(&mut self) -> Result<bool> {
+        while poll(Duration::from_secs(0))? {
+            let event = read()?;
+            self.record(&event);
+            match event {
+                Event::Key(KeyEvent {
+                    code: KeyCode::Esc, ..
+                })
+                | Event::Key(KeyEvent {
+                    code: KeyCode::Char('c'),
+                    modifiers: KeyModifiers::CONTROL,
+                }) => {
+                    self.stop_queued_keys();
+                    self.typed_ahead.clear();
+                    self.status_message = String::from("Interrupted");
+                    return Ok(true);
+                }
+                event => self.typed_ahead.push_back(event),
+            }
+        }
+        Ok(false)
+    }
+
+    //Whether Normal mode is waiting for a new command
+    fn is_idle(&self) -> bool {
+        self.mode == Mode::Normal
+            && self.pending_keys.is_empty()
+            && self.selected_register.is_none()
+    }
+
+    //Modes that ask something in the status bar
+    fn is_prompt(&self) -> bool {
+        self.mode == Mode::Recovery || self.mode == Mode::Confirm
+    }
+
+    //Handles an event, typed or queued
+    pub(super) fn handle_event(&mut self, event: Event) -> Result<()> {
+        if !self.is_prompt() {
+            self.status_message.clear();
+        }
+        //The keys of a command are kept while it's typed, to repeat it with .
+        //if it changes the buffer. Command-line commands aren't repeated
+        if self.is_idle() {
+            self.change_keys.clear();
+        }
+        if let Event::Key(key) = event {
+            if self.mode != Mode::Command && !self.is_prompt() {
+                self.change_keys.push(key);
+            }
+        }
 
         match self.mode {
             Mode::Normal => self.match_event_normal(event),
             Mode::Command => self.match_event_command(event)?,
             Mode::Insert => self.match_event_insert(event),
+            Mode::Recovery => self.match_event_recovery(event)?,
+            Mode::OperatorPending(operator) => self.match_event_operator_pending(event, operator),
+            Mode::Visual | Mode::VisualLine | Mode::VisualBlock => self.match_event_visual(event),
+            Mode::Confirm => self.match_event_confirm(event),
+        }
+        if self.mode == Mode::Command || self.is_prompt() {
+            self.change_keys.clear();
+        }
+        if self.is_idle() && self.history.has_pending_change() && !self.change_keys.is_empty() {
+            self.repeat_keys = std::mem::take(&mut self.change_keys);
+        }
+        //Everything done outside of Insert mode is a single undo step, and
+        //so is a :s confirming each match
+        if self.mode != Mode::Insert && self.mode != Mode::Confirm {
+            self.commit_change();
+        }
+        if self.edits_since_swap >= UPDATECOUNT {
+            self.write_swap_file();
         }
         Ok(())
     }
+
+    //Types the keys of the last change again, before any other queued
+    //keys. A count replaces the ones the change was typed with, before and
+    //after the operator
+    pub(super) fn repeat_change(&mut self, count: Option<usize>) {
+        let mut keys = self.repeat_keys.clone();
+        if let Some(count) = count {
+            let is_char = |key: &KeyEvent, f: fn(char) -> bool| match key.code {
+                KeyCode::Char(c) => f(c),
+                _ => false,
+            };
+            let count_len = |keys: &[KeyEvent]| {
+                keys.iter()
+                    .take_while(|key| is_char(key, |c| c.is_ascii_digit()))
+                    .count()
+            };
+            //The count goes after the register, if there is one
+            let start = match keys.first() {
+                Some(key) if is_char(key, |c| c == '"') => 2,
+                _ => 0,
+            };
+            let start = start.min(keys.len());
+            let n_digits = count_len(&keys[start..]);
+            let after = start + n_digits;
+            let is_operator = |c| Operator::from_key(c).is_some();
+            if keys.get(after).is_some_and(|key| is_char(key, is_operator)) {
+                let n_digits = count_len(&keys[after + 1..]);
+                keys.drain(after + 1..after + 1 + n_digits);
+            }
+            let digits: Vec<KeyEvent> = count
+                .to_string()
+                .chars()
+                .map(|c| KeyEvent::from(KeyCode::Char(c)))
+                .collect();
+            keys.splice(start..after, digits);
+        }
+        self.queue_keys(keys, 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_helpers::{editor, type_keys};
+    use super::super::Line;
+
+    #[test]
+    fn repeat_insert() {
+        let mut editor = editor(&["a"]);
+        type_keys(&mut editor, "Ab<Esc>j.");
+        assert_eq!(editor.buffer, vec![Line::new("abb")]);
+        type_keys(&mut editor, "ox<Esc>3.");
+        assert_eq!(
+            editor.buffer,
+            vec![
+                Line::new("abb"),
+                Line::new("x"),
+                Line::new("x"),
+                Line::new("x"),
+                Line::new("x")
+            ]
+        );
+        editor.undo(1);
+        assert_eq!(editor.buffer.len(), 2);
+    }
+
+    #[test]
+    fn repeat_change() {
+        let mut editor = editor(&["foo bar baz qux"]);
+        type_keys(&mut editor, "cwx<Esc>w.");
+        assert_eq!(editor.buffer, vec![Line::new("x x baz qux")]);
+        type_keys(&mut editor, "w2.");
+        assert_eq!(editor.buffer, vec![Line::new("x x x")]);
+    }
+
+    #[test]
+    fn repeat_delete_with_count() {
+        let mut editor = editor(&["a b c d e f g"]);
+        type_keys(&mut editor, "2dw.");
+        assert_eq!(editor.buffer, vec![Line::new("e f g")]);
+        type_keys(&mut editor, "u03.");
+        assert_eq!(editor.buffer, vec![Line::new("f g")]);
+    }
+
+    #[test]
+    fn repeat_count_after_operator() {
+        let mut editor = editor(&["a b c d e f g h i j"]);
+        type_keys(&mut editor, "d2w3.");
+        assert_eq!(editor.buffer, vec![Line::new("f g h i j")]);
+        type_keys(&mut editor, "uu2d2w.");
+        assert_eq!(editor.buffer, vec![Line::new("i j")]);
+    }
+
+    #[test]
+    fn motions_and_undo_are_not_changes() {
+        let mut editor = editor(&["abc"]);
+        type_keys(&mut editor, "xul.");
+        assert_eq!(editor.buffer, vec![Line::new("ac")]);
+    }
 }
@@ -1,43 +1,199 @@
-use super::{Editor, Line};
+use super::{encoding, Buffer, Editor, History, Line};
 use crossterm::Result;
-use std::fs::File;
+use encoding_rs::{Encoding, UTF_8};
+use std::fs::{self, File, OpenOptions};
 use std::io;
-use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process;
 
 impl Editor {
     pub(super) fn load_to_buf(&mut self, path: &str) -> io::Result<()> {
+        self.load_to_buf_as(path, None)
+    }
+
+    //Loads the file decoding it from encoding, or from the detected one if it's None
+    pub(super) fn load_to_buf_as(
+        &mut self,
+        path: &str,
+        encoding: Option<&'static Encoding>,
+    ) -> io::Result<()> {
+        let mut options = self.buffer.options().clone();
+        options.readonly = false;
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        let encoding = encoding.unwrap_or_else(|| encoding::detect(&bytes));
+        let (text, had_errors) = encoding::decode(&bytes, encoding);
+        options.fileencoding = encoding;
         self.file_name = path.to_owned();
-        match File::open(&self.file_name) {
-            Ok(file) => {
-                self.buffer = io::BufReader::new(file)
-                    .lines()
-                    .map(|line_result| line_result.map(|line| Line::new(&line)))
-                    .collect::<io::Result<Vec<Line>>>()?;
+        self.buffer = Buffer::with_options(&text, options);
+        if self.buffer.is_empty() {
+            self.buffer.push(Line::new(""));
+        }
+        //An empty file is written back empty, not as a single line break
+        if bytes.is_empty() {
+            self.buffer.options_mut().endofline = false;
+        }
+        if had_errors {
+            self.status_message = format!(
+                "\"{}\" [invalid {} bytes replaced]",
+                path,
+                encoding::name(encoding)
+            );
+        } else if encoding != UTF_8 {
+            self.status_message =
+                format!("\"{}\" [converted from {}]", path, encoding::name(encoding));
+        }
+        self.history = History::new();
+        self.read_undo_file();
+        self.edits_since_swap = 0;
+        if self.swap_file_exists() {
+            self.ask_about_swap_file();
+        }
+        Ok(())
+    }
+
+    pub(super) fn save_to_file(&mut self) -> Result<()> {
+        self.write_buffer_to(&self.file_name)?;
+        self.history.mark_saved();
+        Ok(())
+    }
+
+    fn write_buffer_to(&self, path: &str) -> io::Result<()> {
+        let buffer = &self.buffer;
+        write_atomically(path, |file| buffer.write_to(file).map(|_| ()))
+    }
+
+    //Saves the text and its undo file, reporting the result in the status
+    //bar. Returns whether the text was saved, even if the undo file wasn't.
+    //With a path, a copy of the text is written there instead, which doesn't
+    //replace an existing file unless forced. A buffer without a name takes it
+    pub(super) fn write_file(&mut self, force: bool, path: &str) -> bool {
+        if !path.is_empty() && path != self.file_name {
+            if !self.file_name.is_empty() {
+                return self.write_copy(force, path);
+            }
+            if self.refuse_overwrite(force, path) {
+                return false;
+            }
+            self.file_name = path.to_owned();
+        }
+        if self.buffer.options().readonly && !force {
+            self.status_message = String::from("'readonly' option is set (add ! to override)");
+            return false;
+        }
+        match self.save_to_file() {
+            Ok(()) => {
+                self.status_message =
+                    format!("\"{}\" {}L written", self.file_name, self.buffer.len());
+                if let Err(err) = self.write_undo_file() {
+                    self.status_message += &format!(". Can't write undo file: {}", err);
+                }
+                true
             }
-            Err(err) if err.kind() == io::ErrorKind::NotFound => self.buffer = vec![Line::new("")],
+            Err(err) => {
+                self.status_message = format!("Can't write \"{}\": {}", self.file_name, err);
+                false
+            }
+        }
+    }
+
+    fn refuse_overwrite(&mut self, force: bool, path: &str) -> bool {
+        let refused = !force && Path::new(path).exists();
+        if refused {
+            self.status_message = String::from("File exists (add ! to override)");
+        }
+        refused
+    }
+
+    fn write_copy(&mut self, force: bool, path: &str) -> bool {
+        if self.refuse_overwrite(force, path) {
+            return false;
+        }
+        match self.write_buffer_to(path) {
+            Ok(()) => {
+                self.status_message = format!("\"{}\" {}L written", path, self.buffer.len());
+                true
+            }
+            Err(err) => {
+                self.status_message = format!("Can't write \"{}\": {}", path, err);
+                false
+            }
+        }
+    }
+}
+
+fn create_temp_file(dir: &Path, name: &str) -> io::Result<(PathBuf, File)> {
+    let mut n = 0;
+    loop {
+        let path = dir.join(format!(".{}.{}-{}.tmp", name, process::id(), n));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => n += 1,
             Err(err) => return Err(err),
         }
-        Ok(())
     }
+}
 
-    pub(super) fn save_to_file(&self) -> Result<()> {
-        let mut file = File::create(&self.file_name)?;
-        for line in self.buffer.iter() {
-            file.write(line.get_content().as_bytes())?;
-            file.write("\n".as_bytes())?;
+//Writes to a temporary file in the same directory and renames it over path
+//once it is on disk, so a crash or a full disk never leave path half written.
+//The permissions and owner of the file being replaced are kept
+pub(super) fn write_atomically<F>(path: &str, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut io::BufWriter<File>) -> io::Result<()>,
+{
+    //The file a symlink points to is replaced, not the symlink
+    let target = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+    let dir = match target.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_owned(),
+        _ => PathBuf::from("."),
+    };
+    let name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let (temp_path, file) = create_temp_file(&dir, &name)?;
+    let result = (|| {
+        let mut writer = io::BufWriter::new(file);
+        write(&mut writer)?;
+        let file = writer.into_inner().map_err(|err| err.into_error())?;
+        if let Ok(metadata) = fs::metadata(&target) {
+            file.set_permissions(metadata.permissions())?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::{fchown, MetadataExt};
+                //Only root can give a file to another user, so this may fail
+                let _ = fchown(&file, Some(metadata.uid()), Some(metadata.gid()));
+            }
+        }
+        file.sync_all()?;
+        fs::rename(&temp_path, &target)
+    })();
+    match result {
+        Ok(()) => {
+            //Makes the rename itself survive a crash
+            #[cfg(unix)]
+            let _ = File::open(&dir).and_then(|dir| dir.sync_all());
+            Ok(())
+        }
+        Err(err) => {
+            let _ = fs::remove_file(&temp_path);
+            Err(err)
         }
-        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::prelude::*;
     #[test]
     fn load_from_file() -> Result<()> {
         let name = "TestFileWithANameUnnecessarilyLongToAvoidCollisions";
         let mut file = File::create(name)?;
-        file.write(b"This is a line\nAnd this is another line")?;
+        file.write_all(b"This is a line\nAnd this is another line")?;
         let mut editor = Editor::new();
         editor.load_to_buf(name)?;
         assert_eq!(
@@ -51,6 +207,155 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn save_keeps_line_endings() -> Result<()> {
+        let name = "TestFileWithANameUnnecessarilyLongToAvoidCollisions5";
+        std::fs::write(name, "\u{feff}a\r\nb")?;
+        let mut editor = Editor::new();
+        editor.load_to_buf(name)?;
+        assert_eq!(editor.buffer, vec![Line::new("a"), Line::new("b")]);
+        editor.save_to_file()?;
+        assert_eq!(std::fs::read(name)?, "\u{feff}a\r\nb".as_bytes());
+        editor.run_command(":set ff=unix eol")?;
+        editor.save_to_file()?;
+        assert_eq!(std::fs::read(name)?, "\u{feff}a\nb\n".as_bytes());
+        std::fs::remove_file(name)?;
+        Ok(())
+    }
+
+    #[test]
+    fn save_empty_file() -> Result<()> {
+        let name = "TestFileWithANameUnnecessarilyLongToAvoidCollisions11";
+        std::fs::write(name, "")?;
+        let mut editor = Editor::new();
+        editor.load_to_buf(name)?;
+        editor.save_to_file()?;
+        assert_eq!(std::fs::read(name)?, b"");
+        std::fs::remove_file(name)?;
+        Ok(())
+    }
+
+    #[test]
+    fn load_latin1() -> Result<()> {
+        let name = "TestFileWithANameUnnecessarilyLongToAvoidCollisions6";
+        fs::write(name, b"a\xf1o\n")?;
+        let mut editor = Editor::new();
+        editor.load_to_buf(name)?;
+        assert_eq!(editor.buffer, vec![Line::new("año")]);
+        assert_eq!(
+            editor.status_message,
+            format!("\"{}\" [converted from windows-1252]", name)
+        );
+        editor.set_line(0, Line::new("añoñ"));
+        assert!(editor.write_file(false, ""));
+        assert_eq!(fs::read(name)?, b"a\xf1o\xf1\n");
+        fs::remove_file(name)?;
+        fs::remove_file(format!(".{}.un~", name))?;
+        Ok(())
+    }
+
+    #[test]
+    fn load_forced_encoding() -> Result<()> {
+        let name = "TestFileWithANameUnnecessarilyLongToAvoidCollisions7";
+        fs::write(name, "año\n")?;
+        let mut editor = Editor::new();
+        editor.load_to_buf_as(name, Encoding::for_label(b"latin1"))?;
+        assert_eq!(editor.buffer, vec![Line::new("aÃ±o")]);
+        fs::remove_file(name)?;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn save_keeps_permissions() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let name = "TestFileWithANameUnnecessarilyLongToAvoidCollisions8";
+        fs::write(name, "a\n")?;
+        fs::set_permissions(name, fs::Permissions::from_mode(0o600))?;
+        let mut editor = Editor::new();
+        editor.load_to_buf(name)?;
+        editor.save_to_file()?;
+        assert_eq!(fs::metadata(name)?.permissions().mode() & 0o777, 0o600);
+        fs::remove_file(name)?;
+        Ok(())
+    }
+
+    #[test]
+    fn save_failure_is_reported() {
+        let mut editor = Editor::new();
+        editor.buffer.push(Line::new("a"));
+        editor.file_name = String::from("DirectoryThatDoesNotExist/file");
+        assert!(!editor.write_file(false, ""));
+        assert!(editor
+            .status_message
+            .starts_with("Can't write \"DirectoryThatDoesNotExist/file\""));
+    }
+
+    #[test]
+    fn undo_file_failure_still_saves() -> Result<()> {
+        let name = "TestFileWithANameUnnecessarilyLongToAvoidCollisions12";
+        let mut editor = Editor::new();
+        editor.file_name = name.to_owned();
+        editor.buffer.push(Line::new("old"));
+        editor.set_line(0, Line::new("new"));
+        editor.commit_change();
+        //A directory where the undo file should be can't be replaced
+        let undo_dir = format!(".{}.un~", name);
+        fs::create_dir(&undo_dir)?;
+        fs::write(format!("{}/file", undo_dir), "")?;
+        let saved = editor.write_file(false, "");
+        let contents = fs::read(name);
+        fs::remove_dir_all(&undo_dir)?;
+        fs::remove_file(name)?;
+        assert!(saved);
+        assert_eq!(contents?, b"new\n");
+        assert!(editor
+            .status_message
+            .starts_with(&format!("\"{}\" 1L written. Can't write undo file: ", name)));
+        assert!(!editor.history.is_modified());
+        Ok(())
+    }
+
+    #[test]
+    fn write_copy() -> Result<()> {
+        let name = "TestFileWithANameUnnecessarilyLongToAvoidCollisions14";
+        let copy = "TestFileWithANameUnnecessarilyLongToAvoidCollisions15";
+        let mut editor = Editor::new();
+        editor.file_name = name.to_owned();
+        editor.buffer.push(Line::new("a"));
+        editor.set_line(0, Line::new("b"));
+        editor.commit_change();
+        editor.run_command(&format!(":w {}", copy))?;
+        assert_eq!(editor.status_message, format!("\"{}\" 1L written", copy));
+        assert_eq!(fs::read(copy)?, b"b\n");
+        assert!(!Path::new(name).exists());
+        assert!(editor.history.is_modified());
+        editor.set_line(0, Line::new("c"));
+        editor.run_command(&format!(":w {}", copy))?;
+        assert_eq!(editor.status_message, "File exists (add ! to override)");
+        editor.run_command(&format!(":w! {}", copy))?;
+        assert_eq!(fs::read(copy)?, b"c\n");
+        fs::remove_file(copy)?;
+        Ok(())
+    }
+
+    #[test]
+    fn write_names_unnamed_buffer() -> Result<()> {
+        let name = "TestFileWithANameUnnecessarilyLongToAvoidCollisions16";
+        let mut editor = Editor::new();
+        editor.buffer.push(Line::new("a"));
+        editor.set_line(0, Line::new("b"));
+        editor.commit_change();
+        editor.run_command(&format!(":w {}", name))?;
+        assert_eq!(editor.file_name, name);
+        assert_eq!(editor.status_message, format!("\"{}\" 1L written", name));
+        assert_eq!(fs::read(name)?, b"b\n");
+        assert!(!editor.history.is_modified());
+        fs::remove_file(name)?;
+        fs::remove_file(format!(".{}.un~", name))?;
+        Ok(())
+    }
+
     #[test]
     fn save() -> Result<()> {
         let mut editor = Editor::new();
@@ -0,0 +1,463 @@
+use super::{Cursor, Editor, Line};
+use std::cmp::min;
+use std::time::{Duration, SystemTime};
+
+//A reversible edit: the lines in y..y + old.len() were replaced by new
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edit {
+    pub(super) y: usize,
+    pub(super) old: Vec<String>,
+    pub(super) new: Vec<String>,
+}
+
+impl Edit {
+    fn inverse(&self) -> Edit {
+        Edit {
+            y: self.y,
+            old: self.new.clone(),
+            new: self.old.clone(),
+        }
+    }
+}
+
+//A group of edits that is undone and redone as a single step,
+//e.g. everything typed during an Insert mode session
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub(super) edits: Vec<Edit>,
+    pub(super) cursor_before: Cursor,
+    pub(super) cursor_after: Cursor,
+}
+
+impl Change {
+    pub(super) fn new(cursor: Cursor) -> Self {
+        Change {
+            edits: Vec::new(),
+            cursor_before: cursor,
+            cursor_after: cursor,
+        }
+    }
+
+    fn push(&mut self, edit: Edit) {
+        //Consecutive edits of the same single line are merged so typing
+        //a word doesn't store one edit per character
+        if let Some(last) = self.edits.last_mut() {
+            if last.y == edit.y
+                && last.new.len() == 1
+                && edit.old == last.new
+                && edit.new.len() == 1
+            {
+                last.new = edit.new;
+                return;
+            }
+        }
+        self.edits.push(edit);
+    }
+}
+
+//A state of the buffer in the undo tree, reached from its parent by applying change
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Node {
+    pub(super) parent: usize,
+    pub(super) change: Change,
+    pub(super) time: SystemTime,
+    //The child redo goes to, the one created or undone most recently
+    pub(super) redo_child: Option<usize>,
+    pub(super) depth: usize,
+}
+
+//Undo tree. Undoing and then doing a new change starts a new branch
+//instead of discarding the undone changes. The index of a node in
+//nodes is its sequence number, the root (0) being the original text
+#[derive(Debug)]
+pub struct History {
+    pub(super) nodes: Vec<Node>,
+    pub(super) current: usize,
+    pending: Option<Change>,
+    //State of the buffer when it was last loaded or saved
+    pub(super) saved: usize,
+}
+
+impl History {
+    pub fn new() -> Self {
+        History {
+            nodes: vec![Node {
+                parent: 0,
+                change: Change::new(Cursor::new()),
+                time: SystemTime::now(),
+                redo_child: None,
+                depth: 0,
+            }],
+            current: 0,
+            pending: None,
+            saved: 0,
+        }
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.saved = self.current;
+    }
+
+    //Whether there are changes since the buffer was last loaded or saved
+    pub fn is_modified(&self) -> bool {
+        self.current != self.saved || self.pending.is_some()
+    }
+
+    //Whether there are edits that aren't an undo step yet
+    pub fn has_pending_change(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    fn is_ancestor(&self, ancestor: usize, mut node: usize) -> bool {
+        while node != ancestor {
+            if node == 0 {
+                return false;
+            }
+            node = self.nodes[node].parent;
+        }
+        true
+    }
+
+    fn leaves(&self) -> Vec<usize> {
+        let mut has_children = vec![false; self.nodes.len()];
+        for node in self.nodes.iter().skip(1) {
+            has_children[node.parent] = true;
+        }
+        (1..self.nodes.len())
+            .filter(|&i| !has_children[i])
+            .collect()
+    }
+
+    //Sequence number of the state `step` older (or newer if !earlier) than the current one
+    fn target(&self, step: UndoStep, earlier: bool) -> usize {
+        match step {
+            UndoStep::Count(n) if earlier => self.current.saturating_sub(n),
+            UndoStep::Count(n) => min(self.current.saturating_add(n), self.nodes.len() - 1),
+            UndoStep::Seconds(secs) => {
+                let time = self.nodes[self.current].time;
+                let time = if earlier {
+                    time.checked_sub(Duration::from_secs(secs))
+                } else {
+                    time.checked_add(Duration::from_secs(secs))
+                };
+                let time = match time {
+                    Some(time) => time,
+                    None => return if earlier { 0 } else { self.nodes.len() - 1 },
+                };
+                if earlier {
+                    (0..=self.current)
+                        .rev()
+                        .find(|&i| self.nodes[i].time <= time)
+                        .unwrap_or(0)
+                } else {
+                    (self.current..self.nodes.len())
+                        .find(|&i| self.nodes[i].time >= time)
+                        .unwrap_or(self.nodes.len() - 1)
+                }
+            }
+        }
+    }
+}
+
+//Argument of :earlier and :later
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UndoStep {
+    Count(usize),
+    Seconds(u64),
+}
+
+impl UndoStep {
+    //Parses "", "N" and "N{s,m,h,d}"
+    pub fn parse(arg: &str) -> Option<UndoStep> {
+        let arg = arg.trim();
+        if arg.is_empty() {
+            return Some(UndoStep::Count(1));
+        }
+        let (number, multiplier) = match arg.chars().last() {
+            Some('s') => (&arg[..arg.len() - 1], 1),
+            Some('m') => (&arg[..arg.len() - 1], 60),
+            Some('h') => (&arg[..arg.len() - 1], 60 * 60),
+            Some('d') => (&arg[..arg.len() - 1], 24 * 60 * 60),
+            _ => return arg.parse().ok().map(UndoStep::Count),
+        };
+        number
+            .parse::<u64>()
+            .ok()
+            .and_then(|n| n.checked_mul(multiplier))
+            .map(UndoStep::Seconds)
+    }
+}
+
+impl Editor {
+    //Every modification of the buffer must go through here to be undoable
+    pub(super) fn replace_lines(&mut self, y: usize, n: usize, new: Vec<Line>) {
+        let edit = Edit {
+            y,
+            old: (y..y + n)
+                .map(|y| self.buffer.line(y).get_content())
+                .collect(),
+            new: new.iter().map(|line| line.get_content()).collect(),
+        };
+        self.buffer.splice(y..y + n, new);
+        self.edits_since_swap += 1;
+        let cursor = self.cursor;
+        self.history
+            .pending
+            .get_or_insert_with(|| Change::new(cursor))
+            .push(edit);
+    }
+
+    pub(super) fn set_line(&mut self, y: usize, line: Line) {
+        self.replace_lines(y, 1, vec![line]);
+    }
+
+    pub(super) fn insert_line(&mut self, y: usize, line: Line) {
+        self.replace_lines(y, 0, vec![line]);
+    }
+
+    pub(super) fn remove_line(&mut self, y: usize) {
+        self.replace_lines(y, 1, Vec::new());
+    }
+
+    //Closes the current group of edits, if any, making it a single undo step
+    pub(super) fn commit_change(&mut self) {
+        if let Some(mut change) = self.history.pending.take() {
+            change.cursor_after = self.cursor;
+            let history = &mut self.history;
+            let parent = history.current;
+            history.nodes.push(Node {
+                parent,
+                change,
+                time: SystemTime::now(),
+                redo_child: None,
+                depth: history.nodes[parent].depth + 1,
+            });
+            history.current = history.nodes.len() - 1;
+            history.nodes[parent].redo_child = Some(history.current);
+        }
+    }
+
+    pub(super) fn undo(&mut self, n: usize) {
+        self.commit_change();
+        for _ in 0..n {
+            if self.history.current == 0 {
+                self.status_message = String::from("Already at oldest change");
+                return;
+            }
+            self.undo_node();
+        }
+    }
+
+    pub(super) fn redo(&mut self, n: usize) {
+        self.commit_change();
+        for _ in 0..n {
+            match self.history.nodes[self.history.current].redo_child {
+                Some(child) => self.redo_node(child),
+                None => {
+                    self.status_message = String::from("Already at newest change");
+                    return;
+                }
+            }
+        }
+    }
+
+    //Travels through the undo tree to the state with the given sequence number
+    fn goto_state(&mut self, target: usize) {
+        self.commit_change();
+        while !self.history.is_ancestor(self.history.current, target) {
+            self.undo_node();
+        }
+        let mut path = Vec::new();
+        let mut node = target;
+        while node != self.history.current {
+            path.push(node);
+            node = self.history.nodes[node].parent;
+        }
+        for node in path.into_iter().rev() {
+            self.redo_node(node);
+        }
+    }
+
+    pub(super) fn earlier(&mut self, step: UndoStep) {
+        self.commit_change();
+        let target = self.history.target(step, true);
+        self.goto_state(target);
+    }
+
+    pub(super) fn later(&mut self, step: UndoStep) {
+        self.commit_change();
+        let target = self.history.target(step, false);
+        self.goto_state(target);
+    }
+
+    //Lists the leaves of the undo tree as "number changes when"
+    pub(super) fn undo_list(&self) -> String {
+        let leaves = self.history.leaves();
+        if leaves.is_empty() {
+            return String::from("Nothing to undo");
+        }
+        let now = SystemTime::now();
+        leaves
+            .iter()
+            .map(|&i| {
+                let node = &self.history.nodes[i];
+                let elapsed = now.duration_since(node.time).unwrap_or_default();
+                format!("{} {} {}s ago", i, node.depth, elapsed.as_secs())
+            })
+            .collect::<Vec<String>>()
+            .join(" | ")
+    }
+
+    fn undo_node(&mut self) {
+        let current = self.history.current;
+        let node = &self.history.nodes[current];
+        let parent = node.parent;
+        let edits: Vec<Edit> = node.change.edits.iter().rev().map(Edit::inverse).collect();
+        self.cursor = node.change.cursor_before;
+        for edit in edits.iter() {
+            self.apply_edit(edit);
+        }
+        self.history.nodes[parent].redo_child = Some(current);
+        self.history.current = parent;
+    }
+
+    fn redo_node(&mut self, node: usize) {
+        let change = self.history.nodes[node].change.clone();
+        for edit in change.edits.iter() {
+            self.apply_edit(edit);
+        }
+        self.cursor = change.cursor_after;
+        let parent = self.history.nodes[node].parent;
+        self.history.nodes[parent].redo_child = Some(node);
+        self.history.current = node;
+    }
+
+    fn apply_edit(&mut self, edit: &Edit) {
+        self.edits_since_swap += 1;
+        self.buffer.splice(
+            edit.y..edit.y + edit.old.len(),
+            edit.new.iter().map(|s| Line::new(s)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Mode;
+    use super::*;
+    use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+
+    fn key(code: KeyCode) -> Event {
+        Event::Key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    #[test]
+    fn undo_insert_session() {
+        let mut editor = Editor::new();
+        editor.buffer.push(Line::new("Frase"));
+        editor.mode = Mode::Insert;
+        for c in "abc".chars() {
+            editor.match_event_insert(key(KeyCode::Char(c)));
+        }
+        editor.match_event_insert(key(KeyCode::Enter));
+        editor.match_event_insert(key(KeyCode::Esc));
+        editor.commit_change();
+        assert_eq!(editor.buffer, vec![Line::new("abc"), Line::new("Frase")]);
+        editor.undo(1);
+        assert_eq!(editor.buffer, vec![Line::new("Frase")]);
+        assert_eq!(editor.cursor, Cursor { x: 0, y: 0 });
+    }
+
+    #[test]
+    fn redo_restores_cursor() {
+        let mut editor = Editor::new();
+        editor.buffer.push(Line::new("Frase"));
+        editor.cursor.x = 5;
+        editor.set_line(0, Line::new("Frase1"));
+        editor.cursor.x = 6;
+        editor.commit_change();
+        editor.undo(1);
+        assert_eq!(editor.cursor, Cursor { x: 5, y: 0 });
+        editor.redo(1);
+        assert_eq!(editor.buffer, vec![Line::new("Frase1")]);
+        assert_eq!(editor.cursor, Cursor { x: 6, y: 0 });
+    }
+
+    #[test]
+    fn new_change_clears_redo() {
+        let mut editor = Editor::new();
+        editor.buffer.push(Line::new(""));
+        editor.insert_line(1, Line::new("a"));
+        editor.commit_change();
+        editor.undo(1);
+        editor.remove_line(0);
+        editor.commit_change();
+        editor.redo(1);
+        assert_eq!(editor.status_message, "Already at newest change");
+        assert!(editor.buffer.is_empty());
+    }
+
+    #[test]
+    fn undo_keeps_branches() {
+        let mut editor = Editor::new();
+        editor.buffer.push(Line::new("one"));
+        editor.set_line(0, Line::new("two"));
+        editor.commit_change();
+        editor.undo(1);
+        editor.set_line(0, Line::new("three"));
+        editor.commit_change();
+        editor.earlier(UndoStep::Count(1));
+        assert_eq!(editor.buffer, vec![Line::new("two")]);
+        editor.earlier(UndoStep::Count(1));
+        assert_eq!(editor.buffer, vec![Line::new("one")]);
+        editor.later(UndoStep::Count(2));
+        assert_eq!(editor.buffer, vec![Line::new("three")]);
+        assert_eq!(editor.history.leaves(), vec![1, 2]);
+        editor.earlier(UndoStep::Count(usize::MAX));
+        assert_eq!(editor.buffer, vec![Line::new("one")]);
+        editor.run_command(":later 18446744073709551615").unwrap();
+        assert_eq!(editor.buffer, vec![Line::new("three")]);
+    }
+
+    #[test]
+    fn earlier_by_time() {
+        let mut editor = Editor::new();
+        editor.buffer.push(Line::new("one"));
+        editor.set_line(0, Line::new("two"));
+        editor.commit_change();
+        editor.history.nodes[1].time -= Duration::from_secs(100);
+        editor.set_line(0, Line::new("three"));
+        editor.commit_change();
+        editor.earlier(UndoStep::Seconds(50));
+        assert_eq!(editor.buffer, vec![Line::new("two")]);
+        editor.later(UndoStep::Seconds(10));
+        assert_eq!(editor.buffer, vec![Line::new("three")]);
+    }
+
+    #[test]
+    fn parse_undo_step() {
+        assert_eq!(UndoStep::parse(""), Some(UndoStep::Count(1)));
+        assert_eq!(UndoStep::parse("3"), Some(UndoStep::Count(3)));
+        assert_eq!(UndoStep::parse("2m"), Some(UndoStep::Seconds(120)));
+        assert_eq!(UndoStep::parse("x"), None);
+        assert_eq!(UndoStep::parse(&format!("{}d", u64::MAX / 2)), None);
+    }
+
+    #[test]
+    fn line_edits_are_merged() {
+        let mut change = Change::new(Cursor::new());
+        for s in &["a", "ab", "abc"] {
+            let old = change
+                .edits
+                .last()
+                .map_or(vec![String::new()], |edit| edit.new.clone());
+            change.push(Edit {
+                y: 0,
+                old,
+                new: vec![s.to_string()],
+            });
+        }
+        assert_eq!(change.edits.len(), 1);
+        assert_eq!(change.edits[0].old, vec![String::new()]);
+    }
+}
@@ -1,34 +1,137 @@
-use super::{Editor, Mode};
-use crossterm::event::{Event, KeyCode, KeyEvent};
+use super::{Editor, Line, Mode};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+
+//Where o and O open a line
+#[derive(Clone, Copy, PartialEq)]
+pub enum OpenLine {
+    Below,
+    Above,
+}
+
+//The keys typed since entering Insert mode, which are typed count - 1 more
+//times on leaving it, so 3ix<Esc> inserts xxx
+#[derive(Default)]
+pub struct InsertSession {
+    count: usize,
+    open_line: Option<OpenLine>,
+    keys: Vec<KeyEvent>,
+    //Lines below the first one of a block changed in Visual Block mode that
+    //had text in it and its column, where the text typed is inserted too
+    block: Option<(Vec<usize>, usize)>,
+}
+
+//The text the keys of an Insert mode session typed
+fn typed_text(keys: &[KeyEvent]) -> String {
+    let mut text = String::new();
+    for key in keys {
+        match key.code {
+            KeyCode::Char(c) => text.push(c),
+            KeyCode::Tab => text.push('\t'),
+            KeyCode::Enter => text.push('\n'),
+            KeyCode::Backspace => {
+                text.pop();
+            }
+            _ => {}
+        }
+    }
+    text
+}
 
 impl Editor {
+    pub(super) fn start_insert(&mut self, count: usize, open_line: Option<OpenLine>) {
+        if let Some(open_line) = open_line {
+            self.open_line(open_line);
+        }
+        self.insert_session = InsertSession {
+            count,
+            open_line,
+            keys: Vec::new(),
+            block: None,
+        };
+        self.mode = Mode::Insert;
+    }
+
+    pub(super) fn start_block_insert(&mut self, lines: Vec<usize>, column: usize) {
+        self.start_insert(1, None);
+        self.insert_session.block = Some((lines, column));
+    }
+
+    fn open_line(&mut self, open_line: OpenLine) {
+        let y = match open_line {
+            OpenLine::Below => self.y() + 1,
+            OpenLine::Above => self.y(),
+        };
+        self.insert_line(y, Line::new(""));
+        self.cursor.x = 0;
+        self.cursor.y = y;
+    }
+
     pub(super) fn match_event_insert(&mut self, event: Event) {
-        match event {
-            Event::Key(KeyEvent {
-                code: KeyCode::Char(c),
-                ..
-            }) => self.insert_char(c),
-            Event::Key(KeyEvent {
-                code: KeyCode::Tab, ..
-            }) => self.insert_char('\t'),
-            Event::Key(KeyEvent {
-                code: KeyCode::Esc, ..
-            }) => {
-                self.mode = Mode::Normal;
-                self.move_cursor_left(1, false);
+        let key = match event {
+            Event::Key(key) => key,
+            _ => return,
+        };
+        if self.inserting_register {
+            self.inserting_register = false;
+            if let KeyCode::Char(name) = key.code {
+                self.insert_register(name);
+            }
+            return;
+        }
+        if key == KeyEvent::new(KeyCode::Char('r'), KeyModifiers::CONTROL) {
+            self.inserting_register = true;
+            return;
+        }
+        if key.code == KeyCode::Esc {
+            let keys = std::mem::take(&mut self.insert_session.keys);
+            let text = typed_text(&keys);
+            if let Some((lines, column)) = self.insert_session.block.take() {
+                for y in lines.into_iter().filter(|_| !text.contains('\n')) {
+                    let mut line = self.buffer.line(y);
+                    line.insert(column, &text);
+                    self.set_line(y, line);
+                }
+            }
+            self.registers.last_insert = text;
+            for _ in 1..self.insert_session.count {
+                if let Some(open_line) = self.insert_session.open_line {
+                    self.open_line(open_line);
+                }
+                for &key in keys.iter() {
+                    self.insert_key(key);
+                }
             }
-            Event::Key(KeyEvent {
-                code: KeyCode::Enter,
-                ..
-            }) => self.insert_new_line(),
-            Event::Key(KeyEvent {
-                code: KeyCode::Backspace,
-                ..
-            }) => self.delete_back(),
-            Event::Key(KeyEvent {
-                code: KeyCode::Delete,
-                ..
-            }) => self.delete(),
+            self.mode = Mode::Normal;
+            self.move_cursor_left(1, false);
+            return;
+        }
+        self.insert_session.keys.push(key);
+        self.insert_key(key);
+    }
+
+    //Types the text of a register, so it's repeated along with the keys
+    fn insert_register(&mut self, name: char) {
+        let text = match self.register(name) {
+            Some(register) => register.text,
+            None => return,
+        };
+        for c in text.chars() {
+            let key = match c {
+                '\n' => KeyEvent::from(KeyCode::Enter),
+                c => KeyEvent::from(KeyCode::Char(c)),
+            };
+            self.insert_session.keys.push(key);
+            self.insert_key(key);
+        }
+    }
+
+    fn insert_key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Char(c) => self.insert_char(c),
+            KeyCode::Tab => self.insert_tab(),
+            KeyCode::Enter => self.insert_new_line(),
+            KeyCode::Backspace => self.delete_back(),
+            KeyCode::Delete => self.delete(),
             _ => {}
         }
     }
@@ -36,15 +139,33 @@ impl Editor {
     fn insert_char(&mut self, c: char) {
         let y = self.y();
         let x = self.x(false);
-        self.buffer[y].insert(x, &c.to_string());
+        let mut line = self.buffer.line(y);
+        line.insert(x, &c.to_string());
+        self.set_line(y, line);
         self.move_cursor_right(1, false);
     }
 
+    //With expandtab, inserts spaces up to the next multiple of shiftwidth
+    fn insert_tab(&mut self) {
+        if !self.buffer.options().expandtab {
+            return self.insert_char('\t');
+        }
+        let y = self.y();
+        let x = self.x(false);
+        let shiftwidth = self.buffer.options().shiftwidth();
+        let n = shiftwidth - x % shiftwidth;
+        let mut line = self.buffer.line(y);
+        line.insert(x, &" ".repeat(n));
+        self.set_line(y, line);
+        self.move_cursor_right(n, false);
+    }
+
     fn insert_new_line(&mut self) {
         let y = self.y();
         let x = self.x(false);
-        let new_line = self.buffer[y].split_off(x);
-        self.buffer.insert(self.y() + 1, new_line);
+        let mut line = self.buffer.line(y);
+        let new_line = line.split_off(x);
+        self.replace_lines(y, 1, vec![line, new_line]);
         self.cursor.x = 0;
         self.move_cursor_down(1);
     }
@@ -53,46 +174,98 @@ impl Editor {
         let x = self.x(false);
         let y = self.y();
         if x != 0 {
-            self.move_cursor_left(1, false);
-            self.buffer[y].remove(x);
+            let mut line = self.buffer.line(y);
+            //With expandtab, spaces back to the previous multiple of
+            //shiftwidth are deleted together, as if they were a tab
+            let shiftwidth = self.buffer.options().shiftwidth();
+            let stop = (x - 1) / shiftwidth * shiftwidth;
+            let soft_tab = self.buffer.options().expandtab;
+            loop {
+                self.move_cursor_left(1, false);
+                let x = self.x(false);
+                let removed_space = line.grapheme_at(x) == Some(" ");
+                line.remove(x);
+                let prev = line.prev_valid_index(x);
+                if !soft_tab
+                    || !removed_space
+                    || x <= stop
+                    || prev.and_then(|prev| line.grapheme_at(prev)) != Some(" ")
+                {
+                    break;
+                }
+            }
+            self.set_line(y, line);
         } else if y != 0 {
             self.move_cursor_up(1);
             let y = self.y();
-            self.cursor.x = self.buffer[y].len();
-            let remaining_line = self.buffer.remove(y + 1);
-            self.buffer[y].push(&remaining_line.get_content());
+            self.cursor.x = self.buffer.line(y).len();
+            let mut line = self.buffer.line(y);
+            line.push(&self.buffer.line(y + 1).get_content());
+            self.replace_lines(y, 2, vec![line]);
         }
     }
 
     fn delete(&mut self) {
         let x = self.x(false);
         let y = self.y();
-        if self.buffer[y].is_empty() && y != 0 {
-            self.buffer.remove(y);
-        } else if x == self.buffer[y].len() {
-            let line = self.buffer[y + 1].get_content();
-            self.buffer[y].push(&line);
-            self.buffer.remove(y + 1);
+        if self.buffer.line(y).is_empty() && y != 0 {
+            self.remove_line(y);
+        } else if x == self.buffer.line(y).len() {
+            if y + 1 < self.buffer.len() {
+                let mut line = self.buffer.line(y);
+                line.push(&self.buffer.line(y + 1).get_content());
+                self.replace_lines(y, 2, vec![line]);
+            }
         } else {
-            self.buffer[y].remove(x);
+            let mut line = self.buffer.line(y);
+            line.remove(x);
+            self.set_line(y, line);
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::super::{Cursor, Line};
+    use super::super::Cursor;
     use super::*;
+    use crossterm::event::KeyModifiers;
     #[test]
     fn insert_char() {
         let mut editor = Editor::new();
         editor.buffer.push(Line::new("Frase"));
         editor.cursor.x = 5;
         editor.insert_char('1');
-        assert_eq!(editor.buffer[0].get_content(), "Frase1");
+        assert_eq!(editor.buffer.line(0).get_content(), "Frase1");
         assert_eq!(editor.cursor.x, 6);
     }
 
+    #[test]
+    fn insert_register() {
+        let mut editor = Editor::new();
+        editor.buffer.push(Line::new(""));
+        editor.start_insert(2, None);
+        let key = |code| Event::Key(KeyEvent::from(code));
+        editor.match_event_insert(key(KeyCode::Char('a')));
+        editor.match_event_insert(Event::Key(KeyEvent::new(
+            KeyCode::Char('r'),
+            KeyModifiers::CONTROL,
+        )));
+        editor.match_event_insert(key(KeyCode::Char('%')));
+        editor.match_event_insert(key(KeyCode::Char('a')));
+        editor.match_event_insert(key(KeyCode::Esc));
+        assert_eq!(editor.buffer, vec![Line::new("aaaa")]);
+        editor.file_name = String::from("f");
+        editor.start_insert(1, None);
+        editor.match_event_insert(Event::Key(KeyEvent::new(
+            KeyCode::Char('r'),
+            KeyModifiers::CONTROL,
+        )));
+        editor.match_event_insert(key(KeyCode::Char('%')));
+        editor.match_event_insert(key(KeyCode::Esc));
+        assert_eq!(editor.buffer, vec![Line::new("aaafa")]);
+        assert_eq!(editor.register('.').unwrap().text, "f");
+    }
+
     #[test]
     fn newline() {
         let mut editor = Editor::new();
@@ -110,10 +283,48 @@ mod tests {
         editor.buffer.push(Line::new("1"));
         editor.cursor.y = 1;
         editor.delete_back();
-        assert_eq!(editor.buffer[0].get_content(), "Frase1");
+        assert_eq!(editor.buffer.line(0).get_content(), "Frase1");
         assert_eq!(editor.cursor, Cursor { x: 5, y: 0 });
     }
 
+    #[test]
+    fn backspace_in_line() {
+        let mut editor = Editor::new();
+        editor.buffer.push(Line::new("Frase"));
+        editor.cursor.x = 5;
+        editor.delete_back();
+        assert_eq!(editor.buffer.line(0).get_content(), "Fras");
+        assert_eq!(editor.cursor, Cursor { x: 4, y: 0 });
+    }
+
+    #[test]
+    fn soft_tab() {
+        let mut editor = Editor::new();
+        editor.buffer.push(Line::new("ab"));
+        editor.buffer.options_mut().expandtab = true;
+        editor.cursor.x = 1;
+        editor.insert_tab();
+        assert_eq!(editor.buffer.line(0).get_content(), "a   b");
+        assert_eq!(editor.cursor, Cursor { x: 4, y: 0 });
+        editor.insert_tab();
+        editor.delete_back();
+        assert_eq!(editor.buffer.line(0).get_content(), "a   b");
+        editor.delete_back();
+        assert_eq!(editor.buffer.line(0).get_content(), "ab");
+    }
+
+    #[test]
+    fn hard_tab() {
+        let mut editor = Editor::new();
+        editor.buffer.push(Line::new("a  b"));
+        editor.cursor.x = 3;
+        editor.insert_tab();
+        assert_eq!(editor.buffer.line(0).get_content(), "a  \tb");
+        editor.cursor.x = 3;
+        editor.delete_back();
+        assert_eq!(editor.buffer.line(0).get_content(), "a \tb");
+    }
+
     #[test]
     fn delete() {
         let mut editor = Editor::new();
@@ -121,7 +332,33 @@ mod tests {
         editor.buffer.push(Line::new("1"));
         editor.cursor.x = 5;
         editor.delete();
-        assert_eq!(editor.buffer[0].get_content(), "Frase1");
+        assert_eq!(editor.buffer.line(0).get_content(), "Frase1");
+        assert_eq!(editor.cursor, Cursor { x: 5, y: 0 });
+    }
+
+    #[test]
+    fn repeated_insert() {
+        let mut editor = Editor::new();
+        editor.buffer.push(Line::new("ab"));
+        editor.start_insert(3, None);
+        for code in [KeyCode::Char('x'), KeyCode::Char('y'), KeyCode::Esc] {
+            editor.match_event_insert(Event::Key(KeyEvent::new(code, KeyModifiers::NONE)));
+        }
+        assert_eq!(editor.buffer, vec![Line::new("xyxyxyab")]);
         assert_eq!(editor.cursor, Cursor { x: 5, y: 0 });
     }
+
+    #[test]
+    fn repeated_open_line() {
+        let mut editor = Editor::new();
+        editor.buffer.push(Line::new("a"));
+        editor.start_insert(2, Some(OpenLine::Above));
+        for code in [KeyCode::Char('b'), KeyCode::Esc] {
+            editor.match_event_insert(Event::Key(KeyEvent::new(code, KeyModifiers::NONE)));
+        }
+        assert_eq!(
+            editor.buffer,
+            vec![Line::new("b"), Line::new("b"), Line::new("a")]
+        );
+    }
 }
@@ -0,0 +1,314 @@
+use super::registers::Register;
+use super::Editor;
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+
+//How many macros can be running inside each other, so a macro that executes
+//itself before its last key can't run forever
+const MAX_DEPTH: usize = 1000;
+
+//Keys typed again count times, by a macro or by .
+pub struct QueuedKeys {
+    keys: Vec<KeyEvent>,
+    next: usize,
+    count: usize,
+}
+
+//Keys written like in vim, as in ihello<Esc>, so macros can be edited as text
+pub fn keys_to_text(keys: &[KeyEvent]) -> String {
+    let mut text = String::new();
+    for key in keys {
+        let name = match key.code {
+            KeyCode::Char('<') if key.modifiers.is_empty() => String::from("lt"),
+            KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) => format!("C-{}", c),
+            KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::ALT) => format!("M-{}", c),
+            KeyCode::Char(c) => {
+                text.push(c);
+                continue;
+            }
+            KeyCode::Esc => String::from("Esc"),
+            KeyCode::Enter => String::from("CR"),
+            KeyCode::Backspace => String::from("BS"),
+            KeyCode::Tab => String::from("Tab"),
+            KeyCode::BackTab => String::from("S-Tab"),
+            KeyCode::Delete => String::from("Del"),
+            KeyCode::Insert => String::from("Insert"),
+            KeyCode::Left => String::from("Left"),
+            KeyCode::Right => String::from("Right"),
+            KeyCode::Up => String::from("Up"),
+            KeyCode::Down => String::from("Down"),
+            KeyCode::Home => String::from("Home"),
+            KeyCode::End => String::from("End"),
+            KeyCode::PageUp => String::from("PageUp"),
+            KeyCode::PageDown => String::from("PageDown"),
+            KeyCode::F(n) => format!("F{}", n),
+            KeyCode::Null => String::from("Nul"),
+        };
+        text.push('<');
+        text.push_str(&name);
+        text.push('>');
+    }
+    text
+}
+
+//The key named inside <>, like Esc or C-r
+fn named_key(name: &str) -> Option<KeyEvent> {
+    let code = match name.to_ascii_lowercase().as_ref() {
+        "lt" => KeyCode::Char('<'),
+        "esc" => KeyCode::Esc,
+        "cr" | "enter" | "return" => KeyCode::Enter,
+        "bs" => KeyCode::Backspace,
+        "tab" => KeyCode::Tab,
+        "s-tab" => KeyCode::BackTab,
+        "del" => KeyCode::Delete,
+        "insert" => KeyCode::Insert,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "nul" => KeyCode::Null,
+        lower => {
+            if let Some(n) = lower.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                KeyCode::F(n)
+            } else {
+                let modifiers = match lower.get(..2) {
+                    Some("c-") => KeyModifiers::CONTROL,
+                    Some("m-") | Some("a-") => KeyModifiers::ALT,
+                    _ => return None,
+                };
+                let mut chars = name[2..].chars();
+                return match (chars.next(), chars.next()) {
+                    (Some(c), None) => Some(KeyEvent::new(KeyCode::Char(c), modifiers)),
+                    _ => None,
+                };
+            }
+        }
+    };
+    Some(KeyEvent::from(code))
+}
+
+//The keys of text written with keys_to_text. A < that doesn't start the
+//name of a key is typed as is, and line breaks are typed as Enter
+pub fn text_to_keys(text: &str) -> Vec<KeyEvent> {
+    let mut keys = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let named = match c {
+            '<' => rest[1..]
+                .find('>')
+                .and_then(|end| Some((named_key(&rest[1..end + 1])?, end + 2))),
+            _ => None,
+        };
+        if let Some((key, len)) = named {
+            keys.push(key);
+            rest = &rest[len..];
+            continue;
+        }
+        keys.push(match c {
+            '\n' => KeyEvent::from(KeyCode::Enter),
+            c => KeyEvent::from(KeyCode::Char(c)),
+        });
+        rest = &rest[c.len_utf8()..];
+    }
+    keys
+}
+
+impl Editor {
+    //Adds a typed event to the macro being recorded, if any
+    pub(super) fn record(&mut self, event: &Event) {
+        if let (Some((_, keys)), Event::Key(key)) = (&mut self.recording, event) {
+            keys.push(*key);
+        }
+    }
+
+    pub(super) fn start_recording(&mut self, name: char) {
+        if name.is_ascii_alphanumeric() {
+            self.recording = Some((name, Vec::new()));
+        }
+    }
+
+    //Stores the recorded keys but the q that stops the recording
+    pub(super) fn stop_recording(&mut self) {
+        if let Some((name, mut keys)) = self.recording.take() {
+            keys.pop();
+            let register = Register {
+                text: keys_to_text(&keys),
+                linewise: false,
+            };
+            self.registers.write(name, register);
+        }
+    }
+
+    //Types the keys of a register count times, before any other queued
+    //keys. @ is the last register executed and : the last command line
+    pub(super) fn execute_register(&mut self, name: char, count: usize) {
+        let name = match name {
+            '@' => match self.last_macro {
+                Some(name) => name,
+                None => {
+                    self.status_message = String::from("No previously used register");
+                    return;
+                }
+            },
+            name => name,
+        };
+        let text = match name {
+            ':' => self
+                .register(':')
+                .map(|register| format!(":{}\n", register.text)),
+            name => self.register(name).map(|register| match register.linewise {
+                true => register.text + "\n",
+                false => register.text,
+            }),
+        };
+        let text = match text {
+            Some(text) => text,
+            None => {
+                self.status_message = format!("Nothing in register {}", name);
+                return;
+            }
+        };
+        self.last_macro = Some(name);
+        self.queue_keys(text_to_keys(&text), count);
+    }
+
+    //Types keys count times before any other queued keys. They are copied
+    //one time at a time, as they are handled
+    pub(super) fn queue_keys(&mut self, keys: Vec<KeyEvent>, count: usize) {
+        if keys.is_empty() || count == 0 {
+            return;
+        }
+        if self.queued_keys.len() >= MAX_DEPTH {
+            self.stop_queued_keys();
+            self.status_message = String::from("Command too recursive");
+            return;
+        }
+        self.queued_keys.push(QueuedKeys {
+            keys,
+            next: 0,
+            count,
+        });
+    }
+
+    //The next queued key. Keys that have been typed all the times they had
+    //to be are dropped right away, so a macro executing itself as its last
+    //key doesn't go deeper
+    pub(super) fn next_queued_key(&mut self) -> Option<KeyEvent> {
+        let queued = self.queued_keys.last_mut()?;
+        let key = queued.keys[queued.next];
+        queued.next += 1;
+        if queued.next == queued.keys.len() {
+            queued.next = 0;
+            queued.count -= 1;
+            if queued.count == 0 {
+                self.queued_keys.pop();
+            }
+        }
+        Some(key)
+    }
+
+    pub(super) fn stop_queued_keys(&mut self) {
+        self.queued_keys.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_helpers::{editor, type_keys};
+    use super::super::Line;
+    use super::*;
+
+    #[test]
+    fn key_notation() {
+        let keys = vec![
+            KeyEvent::from(KeyCode::Char('i')),
+            KeyEvent::from(KeyCode::Char('<')),
+            KeyEvent::new(KeyCode::Char('r'), KeyModifiers::CONTROL),
+            KeyEvent::from(KeyCode::Esc),
+            KeyEvent::from(KeyCode::F(2)),
+        ];
+        let text = keys_to_text(&keys);
+        assert_eq!(text, "i<lt><C-r><Esc><F2>");
+        assert_eq!(text_to_keys(&text), keys);
+        assert_eq!(
+            text_to_keys("<x><\n"),
+            ['<', 'x', '>', '<']
+                .iter()
+                .map(|&c| KeyEvent::from(KeyCode::Char(c)))
+                .chain(Some(KeyEvent::from(KeyCode::Enter)))
+                .collect::<Vec<KeyEvent>>()
+        );
+    }
+
+    #[test]
+    fn record_and_execute() {
+        let mut editor = Editor::new();
+        editor.buffer.push(Line::new("a"));
+        type_keys(&mut editor, "qqA!<Esc>q");
+        assert_eq!(editor.register('q').unwrap().text, "A!<Esc>");
+        assert_eq!(editor.register('"'), None);
+        type_keys(&mut editor, "2@q@@");
+        assert_eq!(editor.buffer, vec![Line::new("a!!!!")]);
+        type_keys(&mut editor, "u");
+        assert_eq!(editor.buffer, vec![Line::new("a!!!")]);
+    }
+
+    #[test]
+    fn execute_edited_text() {
+        let mut editor = Editor::new();
+        editor.buffer.push(Line::new("a"));
+        editor.buffer.push(Line::new("b"));
+        type_keys(&mut editor, "oix<lt>lt><lt>Esc><Esc>0\"zd$");
+        assert_eq!(editor.register('z').unwrap().text, "ix<lt><Esc>");
+        type_keys(&mut editor, "gg@z");
+        assert_eq!(
+            editor.buffer,
+            vec![Line::new("x<a"), Line::new(""), Line::new("b")]
+        );
+    }
+
+    #[test]
+    fn nested_macros() {
+        let mut editor = editor(&["a"]);
+        editor.registers.write('b', register("A-<Esc>"));
+        editor.registers.write('a', register("2@bA!<Esc>"));
+        type_keys(&mut editor, "2@a");
+        assert_eq!(editor.buffer, vec![Line::new("a--!--!")]);
+    }
+
+    #[test]
+    fn recursive_macro() {
+        let mut editor = editor(&["a"; 2000]);
+        editor.registers.write('a', register("A!<Esc>j@a"));
+        type_keys(&mut editor, "@a");
+        assert_eq!(editor.buffer.line(1999), Line::new("a!"));
+        assert_eq!(editor.status_message, "");
+        editor.registers.write('b', register("@bx"));
+        type_keys(&mut editor, "@b");
+        assert_eq!(editor.status_message, "Command too recursive");
+        assert!(editor.queued_keys.is_empty());
+    }
+
+    #[test]
+    fn failed_motion_stops_macro() {
+        let mut editor = Editor::new();
+        for line in ["a", "b", "c"] {
+            editor.buffer.push(Line::new(line));
+        }
+        type_keys(&mut editor, "qaA!<Esc>jq10@a");
+        assert_eq!(
+            editor.buffer,
+            vec![Line::new("a!"), Line::new("b!"), Line::new("c!")]
+        );
+    }
+
+    fn register(text: &str) -> Register {
+        Register {
+            text: text.to_owned(),
+            linewise: false,
+        }
+    }
+}
@@ -1,38 +1,105 @@
-use super::{line::Line, modes::Mode};
+use super::{
+    buffer::Buffer,
+    encoding,
+    line::Line,
+    modes::{Mode, Operator},
+};
 use crossterm::{
+    event::{Event, KeyEvent},
     execute,
     terminal::{enable_raw_mode, EnterAlternateScreen},
     Result,
 };
+use std::collections::VecDeque;
 use std::env;
 use std::io;
 use std::io::prelude::*;
+mod clipboard;
 mod cursor;
 use cursor::Cursor;
 mod command;
+mod config;
 mod event;
 mod file;
+mod history;
+use history::History;
 mod insert;
+mod macros;
+use insert::InsertSession;
+use macros::QueuedKeys;
+mod motion;
+use motion::CharSearch;
 mod normal;
+mod operator;
+mod registers;
+use registers::Registers;
 mod screen;
+mod search;
+use search::Search;
+mod substitute;
+use substitute::Substitution;
+mod swap;
+mod textobject;
+mod undofile;
+mod visual;
 
 pub struct Editor {
-    buffer: Vec<Line>,
+    buffer: Buffer,
     cursor: Cursor,
     y_scroll: usize,
+    //Columns and rows of the terminal when the screen was last drawn
+    screen_size: (u16, u16),
     file_name: String,
     mode: Mode,
     command_buffer: String,
+    status_message: String,
+    history: History,
+    edits_since_swap: usize,
+    //Keys typed so far of a command that isn't complete yet, like the f of fx
+    pending_keys: String,
+    registers: Registers,
+    //Register chosen with "x for the next command
+    selected_register: Option<char>,
+    //Whether Ctrl-R was typed and the name of the register to insert is next
+    inserting_register: bool,
+    //Count typed before the operator in Operator-pending mode
+    operator_count: Option<usize>,
+    insert_session: InsertSession,
+    last_char_search: Option<CharSearch>,
+    //The end of the selection in Visual mode that doesn't move
+    visual_anchor: Cursor,
+    //First and last lines of the last selection, the '<,'> range
+    visual_lines: Option<(usize, usize)>,
+    //Keys handled before reading more, typed again by . or a macro, with
+    //the innermost macro last, and keys typed while they run
+    queued_keys: Vec<QueuedKeys>,
+    typed_ahead: VecDeque<Event>,
+    //Keys of the command being typed and of the last one that made a change
+    change_keys: Vec<KeyEvent>,
+    repeat_keys: Vec<KeyEvent>,
+    //Register a macro is being recorded to and the keys typed so far
+    recording: Option<(char, Vec<KeyEvent>)>,
+    last_macro: Option<char>,
+    last_search: Option<Search>,
+    //Whether the matches of the last search are drawn highlighted
+    highlight_search: bool,
+    //Where the cursor was when / or ? was typed, to go back if cancelled
+    search_start: Cursor,
+    //Patterns searched for, oldest first, and the one shown in the command line
+    search_history: Vec<String>,
+    search_history_index: usize,
+    //The :s asking for confirmation, and the last replacement, used by ~
+    substitution: Option<Substitution>,
+    last_replacement: String,
 }
 
 impl Editor {
     pub fn init() -> Result<()> {
         let mut editor = Editor::new();
         let args: Vec<String> = env::args().collect();
+        editor.read_config()?;
         if args.len() >= 2 {
-            editor.load_to_buf(&args[1]).expect(
-                "Invalid path or file. Keep in mind that tinate can only read Unicode valid files",
-            );
+            editor.load_to_buf(&args[1]).expect("Invalid path or file");
         } else {
             println!("You must call tinate with the name of the file you want to read or create");
             return Ok(());
@@ -40,19 +107,77 @@ impl Editor {
         execute!(io::stdout(), EnterAlternateScreen)?;
         enable_raw_mode()?;
         loop {
-            editor.refresh_screen()?;
+            //Queued keys are handled without drawing the steps between them
+            if editor.queued_keys.is_empty() {
+                editor.refresh_screen()?;
+            }
             editor.process_event()?;
         }
     }
 
     pub fn new() -> Self {
         Editor {
-            buffer: Vec::new(),
+            buffer: Buffer::new(),
             cursor: Cursor::new(),
             y_scroll: 0,
+            screen_size: (80, 24),
             file_name: String::new(),
             mode: Mode::Normal,
             command_buffer: String::new(),
+            status_message: String::new(),
+            history: History::new(),
+            edits_since_swap: 0,
+            pending_keys: String::new(),
+            registers: Registers::default(),
+            selected_register: None,
+            inserting_register: false,
+            operator_count: None,
+            insert_session: InsertSession::default(),
+            last_char_search: None,
+            visual_anchor: Cursor::new(),
+            visual_lines: None,
+            queued_keys: Vec::new(),
+            typed_ahead: VecDeque::new(),
+            change_keys: Vec::new(),
+            repeat_keys: Vec::new(),
+            recording: None,
+            last_macro: None,
+            last_search: None,
+            highlight_search: false,
+            search_start: Cursor::new(),
+            search_history: Vec::new(),
+            search_history_index: 0,
+            substitution: None,
+            last_replacement: String::new(),
+        }
+    }
+}
+
+//Helpers for the tests of the editor modules
+#[cfg(test)]
+mod test_helpers {
+    use super::macros::text_to_keys;
+    use super::{Editor, Line};
+    use crossterm::event::Event;
+
+    pub fn editor(lines: &[&str]) -> Editor {
+        let mut editor = Editor::new();
+        for line in lines {
+            editor.buffer.push(Line::new(line));
+        }
+        editor
+    }
+
+    //Types keys written like in a macro, as in ihello<Esc>, handling the
+    //keys queued by each one like process_event would
+    pub fn type_keys(editor: &mut Editor, keys: &str) {
+        for key in text_to_keys(keys) {
+            let event = Event::Key(key);
+            editor.record(&event);
+            editor.handle_event(event).unwrap();
+            while let Some(key) = editor.next_queued_key() {
+                editor.handle_event(Event::Key(key)).unwrap();
+            }
         }
     }
 }
@@ -0,0 +1,737 @@
+use super::{Cursor, Editor, Operator};
+use std::cmp::min;
+
+//Result of parsing the keys typed so far
+#[derive(Debug, PartialEq)]
+pub enum Parse<T> {
+    Complete(T),
+    //The keys are the start of something, more are needed
+    Pending,
+    Invalid,
+}
+
+//Splits the keys typed so far into the count before the command, if there
+//is one, and the keys of the command. A count can't start with 0, as 0 is
+//also a motion
+pub fn split_count(keys: &str) -> (Option<usize>, &str) {
+    let digits = if keys.starts_with('0') {
+        0
+    } else {
+        keys.find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(keys.len())
+    };
+    let count = keys[..digits].parse().ok();
+    (count, &keys[digits..])
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    LineStart,
+    LineEnd,
+    //With big set, these move over WORDs, runs of non-blank graphemes
+    WordForward { big: bool },
+    WordBackward { big: bool },
+    WordEnd { big: bool },
+    WordEndBackward { big: bool },
+    ParagraphForward,
+    ParagraphBackward,
+    //Line numbers start at 1, like the ones the user sees
+    Line(usize),
+    LastLine,
+    //The line at that percentage of the file
+    Percent(usize),
+    //Top, middle and bottom lines in the screen
+    ScreenTop,
+    ScreenMiddle,
+    ScreenBottom,
+    //repeat is set when it comes from ; or ,
+    Find { search: CharSearch, repeat: bool },
+    //; and , repeat the last f, F, t or T, the latter in the other direction
+    RepeatFind { reverse: bool },
+    //n and N repeat the last search, the latter in the other direction
+    SearchNext { reverse: bool },
+}
+
+//The search for a character in the line of f, F, t and T. With till set,
+//the cursor stops next to the character instead of on it
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct CharSearch {
+    pub c: char,
+    pub forward: bool,
+    pub till: bool,
+}
+
+//How the text between the cursor and the target of a motion is operated on
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MotionKind {
+    //Up to the target, without it
+    Exclusive,
+    //Up to the target, including it
+    Inclusive,
+    //Every line from the cursor to the target
+    Linewise,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RangeKind {
+    Charwise,
+    Linewise,
+}
+
+//The text an operator acts on. For charwise ranges end is exclusive and
+//may be the len of its line, for linewise ones only the ys matter
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct MotionRange {
+    pub start: Cursor,
+    pub end: Cursor,
+    pub kind: RangeKind,
+}
+
+impl Motion {
+    //Motions like G and % behave differently with a count
+    pub fn parse(keys: &str, count: Option<usize>) -> Parse<Motion> {
+        let mut chars = keys.chars();
+        let motion = match chars.next() {
+            Some('h') => Motion::Left,
+            Some('l') => Motion::Right,
+            Some('k') => Motion::Up,
+            Some('j') => Motion::Down,
+            Some('0') => Motion::LineStart,
+            Some('$') => Motion::LineEnd,
+            Some('w') => Motion::WordForward { big: false },
+            Some('W') => Motion::WordForward { big: true },
+            Some('b') => Motion::WordBackward { big: false },
+            Some('B') => Motion::WordBackward { big: true },
+            Some('e') => Motion::WordEnd { big: false },
+            Some('E') => Motion::WordEnd { big: true },
+            Some('g') => match chars.next() {
+                Some('g') => Motion::Line(count.unwrap_or(1)),
+                Some('e') => Motion::WordEndBackward { big: false },
+                Some('E') => Motion::WordEndBackward { big: true },
+                Some(_) => return Parse::Invalid,
+                None => return Parse::Pending,
+            },
+            Some('}') => Motion::ParagraphForward,
+            Some('{') => Motion::ParagraphBackward,
+            Some('G') => count.map_or(Motion::LastLine, Motion::Line),
+            Some('%') => match count {
+                Some(count) if count <= 100 => Motion::Percent(count),
+                _ => return Parse::Invalid,
+            },
+            Some('H') => Motion::ScreenTop,
+            Some('M') => Motion::ScreenMiddle,
+            Some('L') => Motion::ScreenBottom,
+            Some(key @ 'f') | Some(key @ 'F') | Some(key @ 't') | Some(key @ 'T') => {
+                match chars.next() {
+                    Some(c) => Motion::Find {
+                        search: CharSearch {
+                            c,
+                            forward: key.is_lowercase(),
+                            till: key == 't' || key == 'T',
+                        },
+                        repeat: false,
+                    },
+                    None => return Parse::Pending,
+                }
+            }
+            Some(';') => Motion::RepeatFind { reverse: false },
+            Some(',') => Motion::RepeatFind { reverse: true },
+            Some('n') => Motion::SearchNext { reverse: false },
+            Some('N') => Motion::SearchNext { reverse: true },
+            _ => return Parse::Invalid,
+        };
+        match chars.next() {
+            Some(_) => Parse::Invalid,
+            None => Parse::Complete(motion),
+        }
+    }
+
+    pub fn kind(self) -> MotionKind {
+        match self {
+            Motion::Up
+            | Motion::Down
+            | Motion::Line(_)
+            | Motion::LastLine
+            | Motion::Percent(_)
+            | Motion::ScreenTop
+            | Motion::ScreenMiddle
+            | Motion::ScreenBottom => MotionKind::Linewise,
+            Motion::LineEnd
+            | Motion::WordEnd { .. }
+            | Motion::WordEndBackward { .. }
+            | Motion::Find {
+                search: CharSearch { forward: true, .. },
+                ..
+            } => MotionKind::Inclusive,
+            _ => MotionKind::Exclusive,
+        }
+    }
+}
+
+impl Editor {
+    //Index of the last grapheme of line y, 0 if it is empty
+    pub(super) fn last_index(&self, y: usize) -> usize {
+        let line = self.buffer.line(y);
+        line.prev_valid_index(line.len()).unwrap_or(0)
+    }
+
+    //Display indexes of the first and the last grapheme of each word of line y
+    fn word_bounds(&self, y: usize, big: bool) -> Vec<(usize, usize)> {
+        let line = self.buffer.line(y);
+        line.words(big)
+            .into_iter()
+            .map(|word| {
+                let last = line.prev_valid_index(word.end).unwrap_or(word.start);
+                (word.start, last)
+            })
+            .collect()
+    }
+
+    fn is_word_end(&self, pos: Cursor, big: bool) -> bool {
+        self.word_bounds(pos.y, big)
+            .iter()
+            .any(|&(_, last)| last == pos.x)
+    }
+
+    fn is_blank(&self, pos: Cursor) -> bool {
+        self.buffer
+            .line(pos.y)
+            .grapheme_at(pos.x)
+            .is_none_or(|grapheme| grapheme.chars().all(char::is_whitespace))
+    }
+
+    //Where a single step of the motion goes from pos, None if it can't move
+    fn motion_step(&self, motion: Motion, pos: Cursor) -> Option<Cursor> {
+        let Cursor { x, y } = pos;
+        let line = self.buffer.line(y);
+        let last_y = self.buffer.len() - 1;
+        match motion {
+            Motion::Left => line.prev_valid_index(x).map(|x| Cursor { x, y }),
+            Motion::Right => Some(Cursor {
+                x: line.next_valid_index(x).unwrap_or_else(|| line.len()),
+                y,
+            }),
+            //Empty lines count as words for all of them except e
+            Motion::WordForward { big } => {
+                let words = self.word_bounds(y, big);
+                if let Some(&(start, _)) = words.iter().find(|&&(start, _)| start > x) {
+                    return Some(Cursor { x: start, y });
+                }
+                for y in y + 1..=last_y {
+                    match self.word_bounds(y, big).first() {
+                        _ if self.buffer.line(y).is_empty() => return Some(Cursor { x: 0, y }),
+                        Some(&(x, _)) => return Some(Cursor { x, y }),
+                        None => {}
+                    }
+                }
+                let len = self.buffer.line(last_y).len();
+                Some(Cursor { x: len, y: last_y })
+            }
+            Motion::WordBackward { big } => {
+                let words = self.word_bounds(y, big);
+                if let Some(&(start, _)) = words.iter().rev().find(|&&(start, _)| start < x) {
+                    return Some(Cursor { x: start, y });
+                }
+                for y in (0..y).rev() {
+                    match self.word_bounds(y, big).last() {
+                        _ if self.buffer.line(y).is_empty() => return Some(Cursor { x: 0, y }),
+                        Some(&(x, _)) => return Some(Cursor { x, y }),
+                        None => {}
+                    }
+                }
+                Some(Cursor { x: 0, y: 0 })
+            }
+            Motion::WordEnd { big } => {
+                let words = self.word_bounds(y, big);
+                if let Some(&(_, last)) = words.iter().find(|&&(_, last)| last > x) {
+                    return Some(Cursor { x: last, y });
+                }
+                for y in y + 1..=last_y {
+                    if let Some(&(_, x)) = self.word_bounds(y, big).first() {
+                        return Some(Cursor { x, y });
+                    }
+                }
+                Some(Cursor {
+                    x: self.last_index(last_y),
+                    y: last_y,
+                })
+            }
+            Motion::WordEndBackward { big } => {
+                let words = self.word_bounds(y, big);
+                if let Some(&(_, last)) = words.iter().rev().find(|&&(_, last)| last < x) {
+                    return Some(Cursor { x: last, y });
+                }
+                for y in (0..y).rev() {
+                    match self.word_bounds(y, big).last() {
+                        _ if self.buffer.line(y).is_empty() => return Some(Cursor { x: 0, y }),
+                        Some(&(_, x)) => return Some(Cursor { x, y }),
+                        None => {}
+                    }
+                }
+                Some(Cursor { x: 0, y: 0 })
+            }
+            //Paragraphs are separated by empty lines
+            Motion::ParagraphForward => {
+                let is_empty = |y| self.buffer.line(y).is_empty();
+                let mut y = y;
+                while y < last_y && is_empty(y) {
+                    y += 1;
+                }
+                while y < last_y && !is_empty(y) {
+                    y += 1;
+                }
+                let x = if is_empty(y) {
+                    0
+                } else {
+                    self.buffer.line(y).len()
+                };
+                Some(Cursor { x, y })
+            }
+            Motion::ParagraphBackward => {
+                let is_empty = |y| self.buffer.line(y).is_empty();
+                let mut y = y;
+                while y > 0 && is_empty(y) {
+                    y -= 1;
+                }
+                while y > 0 && !is_empty(y) {
+                    y -= 1;
+                }
+                Some(Cursor { x: 0, y })
+            }
+            _ => None,
+        }
+    }
+
+    //Index the count-th occurrence of the character in the line takes the
+    //cursor to. Composing characters are ignored, so fa finds an a with an
+    //accent made of two code points too. A t or T repeated without a count
+    //skips the character the cursor is next to, or it would get stuck there
+    fn find_char(&self, search: CharSearch, count: usize, repeat: bool) -> Option<usize> {
+        let (x, y) = (self.x(true), self.y());
+        let line = self.buffer.line(y);
+        let mut graphemes: Vec<(usize, &str)> = if search.forward {
+            line.graphemes().filter(|&(i, _)| i > x).collect()
+        } else {
+            line.graphemes().filter(|&(i, _)| i < x).collect()
+        };
+        if !search.forward {
+            graphemes.reverse();
+        }
+        let skip = if search.till && repeat && count == 1 {
+            1
+        } else {
+            0
+        };
+        let (i, _) = graphemes
+            .iter()
+            .skip(skip)
+            .filter(|(_, grapheme)| grapheme.starts_with(search.c))
+            .nth(count - 1)?;
+        match (search.till, search.forward) {
+            (false, _) => Some(*i),
+            (true, true) => line.prev_valid_index(*i),
+            (true, false) => line.next_valid_index(*i),
+        }
+    }
+
+    //Turns ; and , into the search they repeat, and remembers the searches
+    //of f, F, t and T for them. None if there is nothing to repeat
+    pub(super) fn resolve_char_search(&mut self, motion: Motion) -> Option<Motion> {
+        match motion {
+            Motion::Find { search, .. } => self.last_char_search = Some(search),
+            Motion::RepeatFind { reverse } => {
+                let mut search = self.last_char_search?;
+                search.forward ^= reverse;
+                return Some(Motion::Find {
+                    search,
+                    repeat: true,
+                });
+            }
+            _ => {}
+        }
+        Some(motion)
+    }
+
+    //Where the motion repeated count times takes the cursor, None if it
+    //can't be done. Charwise targets can be the len of their line, so
+    //operators reach the end
+    pub(super) fn motion_target(&self, motion: Motion, count: usize) -> Option<Cursor> {
+        let (x, y) = (self.x(true), self.y());
+        let last_y = self.buffer.len() - 1;
+        match motion {
+            Motion::Up if y > 0 => Some(Cursor {
+                x: self.cursor.x,
+                y: y.saturating_sub(count),
+            }),
+            Motion::Down if y < last_y => Some(Cursor {
+                x: self.cursor.x,
+                y: min(y.saturating_add(count), last_y),
+            }),
+            Motion::Up | Motion::Down => None,
+            Motion::LineStart => Some(Cursor { x: 0, y }),
+            //2$ goes to the end of the next line
+            Motion::LineEnd => {
+                let y = min(y.saturating_add(count - 1), last_y);
+                Some(Cursor {
+                    x: self.last_index(y),
+                    y,
+                })
+            }
+            Motion::Line(n) => Some(self.line_target(n.max(1) - 1)),
+            Motion::LastLine => Some(self.line_target(last_y)),
+            Motion::Percent(n) => {
+                Some(self.line_target((n * (last_y + 1)).div_ceil(100).max(1) - 1))
+            }
+            Motion::ScreenTop => {
+                let n = min(count, self.screen_lines()) - 1;
+                Some(self.line_target(self.y_scroll + n))
+            }
+            Motion::ScreenMiddle => {
+                Some(self.line_target(self.y_scroll + (self.screen_lines() - 1) / 2))
+            }
+            Motion::ScreenBottom => {
+                let n = self.screen_lines().saturating_sub(count);
+                Some(self.line_target(self.y_scroll + n))
+            }
+            Motion::Find { search, repeat } => self
+                .find_char(search, count, repeat)
+                .map(|x| Cursor { x, y }),
+            Motion::RepeatFind { .. } => None,
+            Motion::SearchNext { reverse } => self.search_next_target(reverse, count),
+            _ => {
+                let mut pos = Cursor { x, y };
+                //Every step moves at least a character, so more are useless
+                for i in 0..min(count, self.buffer.len_chars()) {
+                    match self.motion_step(motion, pos) {
+                        //At the end of the line or the buffer it can't go further
+                        Some(next) if next == pos => break,
+                        Some(next) => pos = next,
+                        //3h goes as far as it can
+                        None if i > 0 => break,
+                        None => return None,
+                    }
+                }
+                Some(pos)
+            }
+        }
+    }
+
+    //The first non-blank of line y, or of the last line if there are fewer
+    fn line_target(&self, y: usize) -> Cursor {
+        let y = min(y, self.buffer.len() - 1);
+        Cursor {
+            x: self.buffer.line(y).indent(),
+            y,
+        }
+    }
+
+    //A motion that fails stops the macro or the repeated change being typed
+    pub(super) fn move_cursor_to(&mut self, motion: Motion, count: usize) {
+        let target = match motion {
+            Motion::SearchNext { reverse } => self.search_next(reverse, count),
+            _ => self.motion_target(motion, count),
+        };
+        match target {
+            Some(target) => {
+                self.cursor = target;
+                //Vertical motions keep the column the cursor wants to be in
+                if motion != Motion::Up && motion != Motion::Down {
+                    self.cursor.x = self.x(true);
+                }
+            }
+            None => self.stop_queued_keys(),
+        }
+    }
+
+    //The text between the cursor and the target of the motion, following
+    //the exceptions vim makes for operators
+    pub(super) fn motion_range(
+        &self,
+        mut motion: Motion,
+        count: usize,
+        operator: Operator,
+    ) -> Option<MotionRange> {
+        let cursor = Cursor {
+            x: self.x(true),
+            y: self.y(),
+        };
+        //cw changes up to the end of the word, like ce, but doesn't go on to
+        //the next word if the cursor is already at the end of one
+        let mut target = None;
+        if let (Operator::Change, Motion::WordForward { big }) = (operator, motion) {
+            if !self.is_blank(cursor) {
+                motion = Motion::WordEnd { big };
+                if self.is_word_end(cursor, big) {
+                    target = match count {
+                        1 => Some(cursor),
+                        _ => self.motion_target(motion, count - 1),
+                    };
+                }
+            }
+        }
+        let target = match target {
+            Some(target) => target,
+            None => self.motion_target(motion, count)?,
+        };
+        let (start, mut end) = if (target.y, target.x) < (cursor.y, cursor.x) {
+            (target, cursor)
+        } else {
+            (cursor, target)
+        };
+        let mut kind = RangeKind::Charwise;
+        match motion.kind() {
+            MotionKind::Linewise => kind = RangeKind::Linewise,
+            MotionKind::Inclusive => {
+                let line = self.buffer.line(end.y);
+                end.x = line.next_valid_index(end.x).unwrap_or_else(|| line.len());
+            }
+            //dw on the last word of a line doesn't join it with the next one
+            MotionKind::Exclusive
+                if matches!(motion, Motion::WordForward { .. }) && end.y > start.y =>
+            {
+                end.y -= 1;
+                end.x = self.buffer.line(end.y).len();
+            }
+            //An exclusive motion ending at the start of a line doesn't take
+            //the line break, and takes whole lines if it started at the indent
+            MotionKind::Exclusive if end.x == 0 && end.y > start.y => {
+                end.y -= 1;
+                end.x = self.buffer.line(end.y).len();
+                if start.x <= self.buffer.line(start.y).indent() {
+                    kind = RangeKind::Linewise;
+                }
+            }
+            MotionKind::Exclusive => {}
+        }
+        Some(MotionRange { start, end, kind })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_helpers::editor;
+    use super::*;
+
+    #[test]
+    fn parse_motions() {
+        assert_eq!(
+            Motion::parse("w", None),
+            Parse::Complete(Motion::WordForward { big: false })
+        );
+        assert_eq!(Motion::parse("f", None), Parse::Pending);
+        assert_eq!(
+            Motion::parse("fá", None),
+            Parse::Complete(Motion::Find {
+                search: CharSearch {
+                    c: 'á',
+                    forward: true,
+                    till: false
+                },
+                repeat: false
+            })
+        );
+        assert_eq!(Motion::parse("z", None), Parse::Invalid);
+        assert_eq!(Motion::parse("ww", None), Parse::Invalid);
+    }
+
+    #[test]
+    fn word_forward() {
+        let mut editor = editor(&["año, más", "", "  x"]);
+        let mut targets = Vec::new();
+        for _ in 0..5 {
+            editor.move_cursor_to(Motion::WordForward { big: false }, 1);
+            targets.push((editor.cursor.x, editor.cursor.y));
+        }
+        assert_eq!(targets, vec![(3, 0), (5, 0), (0, 1), (2, 2), (2, 2)]);
+    }
+
+    #[test]
+    fn word_backward_and_end() {
+        let mut editor = editor(&["foo-bar", "  baz"]);
+        editor.cursor = Cursor { x: 2, y: 1 };
+        editor.move_cursor_to(Motion::WordBackward { big: false }, 1);
+        assert_eq!(editor.cursor, Cursor { x: 4, y: 0 });
+        editor.move_cursor_to(Motion::WordEnd { big: false }, 1);
+        assert_eq!(editor.cursor, Cursor { x: 6, y: 0 });
+        editor.move_cursor_to(Motion::WordEnd { big: false }, 1);
+        assert_eq!(editor.cursor, Cursor { x: 4, y: 1 });
+    }
+
+    fn find(keys: &str) -> Motion {
+        match Motion::parse(keys, None) {
+            Parse::Complete(motion) => motion,
+            _ => panic!("Not a motion: {}", keys),
+        }
+    }
+
+    #[test]
+    fn find_forward() {
+        let mut editor = editor(&["a\tbáb"]);
+        editor.move_cursor_to(find("fb"), 1);
+        assert_eq!(editor.cursor.x, 4);
+        editor.move_cursor_to(find("fz"), 1);
+        assert_eq!(editor.cursor.x, 4);
+    }
+
+    #[test]
+    fn find_graphemes() {
+        let mut editor = editor(&["xa\u{301}yáz"]);
+        editor.move_cursor_to(find("fá"), 1);
+        assert_eq!(editor.cursor.x, 3);
+        editor.move_cursor_to(find("Fa"), 1);
+        assert_eq!(editor.cursor.x, 1);
+        editor.move_cursor_to(find("tz"), 1);
+        assert_eq!(editor.cursor.x, 3);
+    }
+
+    #[test]
+    fn repeat_find() {
+        let mut editor = editor(&["a-b-c-d"]);
+        let motion = editor.resolve_char_search(find("t-"));
+        assert_eq!(
+            editor.resolve_char_search(find(",")),
+            Some(Motion::Find {
+                search: CharSearch {
+                    c: '-',
+                    forward: false,
+                    till: true
+                },
+                repeat: true
+            })
+        );
+        editor.move_cursor_to(motion.unwrap(), 1);
+        assert_eq!(editor.cursor.x, 0);
+        let repeat = editor.resolve_char_search(find(";")).unwrap();
+        editor.move_cursor_to(repeat, 1);
+        assert_eq!(editor.cursor.x, 2);
+        editor.move_cursor_to(repeat, 2);
+        assert_eq!(editor.cursor.x, 4);
+        let range = editor.motion_range(find("F-"), 2, Operator::Delete);
+        assert_eq!(
+            range.map(|range| (range.start.x, range.end.x)),
+            Some((1, 4))
+        );
+    }
+
+    #[test]
+    fn word_range_stops_at_line_end() {
+        let editor = editor(&["foo bar", "baz"]);
+        let range = editor.motion_range(Motion::WordForward { big: false }, 1, Operator::Delete);
+        assert_eq!(
+            range.map(|range| (range.end, range.kind)),
+            Some((Cursor { x: 4, y: 0 }, RangeKind::Charwise))
+        );
+        let mut editor = editor;
+        editor.cursor.x = 4;
+        let range = editor.motion_range(Motion::WordForward { big: false }, 1, Operator::Delete);
+        assert_eq!(range.map(|range| range.end), Some(Cursor { x: 7, y: 0 }));
+    }
+
+    #[test]
+    fn inclusive_range() {
+        let editor = editor(&["foo bar"]);
+        let range = editor.motion_range(Motion::LineEnd, 1, Operator::Delete);
+        assert_eq!(range.map(|range| range.end), Some(Cursor { x: 7, y: 0 }));
+        let range = editor.motion_range(Motion::WordForward { big: false }, 1, Operator::Change);
+        assert_eq!(range.map(|range| range.end), Some(Cursor { x: 3, y: 0 }));
+    }
+
+    #[test]
+    fn failed_motion() {
+        let editor = editor(&["a"]);
+        assert_eq!(editor.motion_range(Motion::Up, 1, Operator::Delete), None);
+        assert_eq!(editor.motion_range(Motion::Left, 1, Operator::Delete), None);
+    }
+
+    #[test]
+    fn counts() {
+        assert_eq!(split_count("10x"), (Some(10), "x"));
+        assert_eq!(split_count("0"), (None, "0"));
+        assert_eq!(split_count("2d3w"), (Some(2), "d3w"));
+        assert_eq!(split_count("12"), (Some(12), ""));
+    }
+
+    #[test]
+    fn counted_motions() {
+        let mut editor = editor(&["a b c d", "e", "f"]);
+        editor.move_cursor_to(Motion::WordForward { big: false }, 3);
+        assert_eq!(editor.cursor, Cursor { x: 6, y: 0 });
+        editor.move_cursor_to(Motion::Left, 10);
+        assert_eq!(editor.cursor.x, 0);
+        editor.move_cursor_to(Motion::Down, 5);
+        assert_eq!(editor.cursor.y, 2);
+        assert_eq!(editor.motion_target(find("fb"), 2), None);
+    }
+
+    #[test]
+    fn huge_counts_stop_at_the_end() {
+        let mut editor = editor(&["abc", "d"]);
+        editor.move_cursor_to(Motion::Right, 9999999999999);
+        assert_eq!(editor.cursor, Cursor { x: 2, y: 0 });
+        editor.move_cursor_to(Motion::WordForward { big: false }, 9999999999999);
+        assert_eq!(editor.cursor.y, 1);
+    }
+
+    #[test]
+    fn big_words() {
+        let mut editor = editor(&["foo-bar baz", "", "qux"]);
+        editor.move_cursor_to(Motion::WordForward { big: true }, 1);
+        assert_eq!(editor.cursor.x, 8);
+        editor.move_cursor_to(Motion::WordEnd { big: true }, 2);
+        assert_eq!(editor.cursor, Cursor { x: 2, y: 2 });
+        editor.move_cursor_to(Motion::WordEndBackward { big: false }, 1);
+        assert_eq!(editor.cursor, Cursor { x: 0, y: 1 });
+        editor.move_cursor_to(Motion::WordEndBackward { big: false }, 2);
+        assert_eq!(editor.cursor, Cursor { x: 6, y: 0 });
+        editor.move_cursor_to(Motion::WordBackward { big: true }, 1);
+        assert_eq!(editor.cursor.x, 0);
+    }
+
+    #[test]
+    fn paragraphs() {
+        let mut editor = editor(&["a", "b", "", "", "c", "d"]);
+        editor.move_cursor_to(Motion::ParagraphForward, 1);
+        assert_eq!(editor.cursor.y, 2);
+        editor.move_cursor_to(Motion::ParagraphForward, 1);
+        assert_eq!(editor.cursor, Cursor { x: 0, y: 5 });
+        editor.move_cursor_to(Motion::ParagraphBackward, 1);
+        assert_eq!(editor.cursor.y, 3);
+        let range = editor.motion_range(Motion::ParagraphBackward, 2, Operator::Delete);
+        assert_eq!(range.map(|range| range.kind), Some(RangeKind::Linewise));
+    }
+
+    #[test]
+    fn line_jumps() {
+        let mut editor = editor(&["a", "  b", "c", "d"]);
+        assert_eq!(
+            Motion::parse("G", Some(2)),
+            Parse::Complete(Motion::Line(2))
+        );
+        assert_eq!(Motion::parse("%", None), Parse::Invalid);
+        editor.move_cursor_to(Motion::Line(2), 1);
+        assert_eq!(editor.cursor, Cursor { x: 2, y: 1 });
+        editor.move_cursor_to(Motion::LastLine, 1);
+        assert_eq!(editor.cursor.y, 3);
+        editor.move_cursor_to(Motion::Percent(50), 1);
+        assert_eq!(editor.cursor.y, 1);
+        editor.move_cursor_to(Motion::Line(10), 1);
+        assert_eq!(editor.cursor.y, 3);
+    }
+
+    #[test]
+    fn screen_lines() {
+        let mut editor = editor(&["a", "b", "c", "d", "e", "f"]);
+        editor.screen_size = (10, 4);
+        editor.y_scroll = 1;
+        editor.move_cursor_to(Motion::ScreenBottom, 1);
+        assert_eq!(editor.cursor.y, 3);
+        editor.move_cursor_to(Motion::ScreenTop, 2);
+        assert_eq!(editor.cursor.y, 2);
+        editor.move_cursor_to(Motion::ScreenMiddle, 1);
+        assert_eq!(editor.cursor.y, 2);
+    }
+}
@@ -1,90 +1,169 @@
-use super::{Editor, Line, Mode};
-use crossterm::event::{Event, KeyCode, KeyEvent};
+use super::insert::OpenLine;
+use super::motion::{split_count, Motion, Parse};
+use super::registers::is_register_name;
+use super::{Editor, Mode, Operator};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+use std::cmp::{max, min};
 
 impl Editor {
+    //Keys are gathered in pending_keys until they make up a command, so
+    //counts and commands of several keys like 3fx can be typed
     pub(super) fn match_event_normal(&mut self, event: Event) {
-        match event {
+        let (c, modifiers) = match event {
             Event::Key(KeyEvent {
-                code: KeyCode::Char(':'),
-                ..
-            }) => {
-                self.mode = Mode::Command;
-                self.command_buffer = String::new();
-                self.command_buffer.push(':');
-            }
-            Event::Key(KeyEvent {
-                code: KeyCode::Char('h'),
-                ..
-            }) => {
-                self.move_cursor_left(1, true);
+                code: KeyCode::Char(c),
+                modifiers,
+            }) => (c, modifiers),
+            _ => {
+                self.pending_keys.clear();
+                self.selected_register = None;
+                return;
             }
-            Event::Key(KeyEvent {
-                code: KeyCode::Char('j'),
-                ..
-            }) => {
-                self.move_cursor_down(1);
+        };
+        if modifiers.contains(KeyModifiers::CONTROL) {
+            let (count, _) = split_count(&self.pending_keys);
+            self.pending_keys.clear();
+            self.selected_register = None;
+            self.match_control_normal(c, count);
+            return;
+        }
+        self.pending_keys.push(c);
+        let (given_count, keys) = split_count(&self.pending_keys);
+        if keys.is_empty() {
+            return;
+        }
+        if keys.starts_with('"') {
+            self.select_register();
+            return;
+        }
+        let count = given_count.unwrap_or(1);
+        if keys == "q" && self.recording.is_some() {
+            self.pending_keys.clear();
+            self.stop_recording();
+            return;
+        }
+        //q{register} starts recording a macro and @{register} executes it
+        if keys.starts_with('q') || keys.starts_with('@') {
+            if let Some(name) = keys.chars().nth(1) {
+                let record = keys.starts_with('q');
+                self.pending_keys.clear();
+                self.selected_register = None;
+                if record {
+                    self.start_recording(name);
+                } else {
+                    self.execute_register(name, count);
+                }
             }
-            Event::Key(KeyEvent {
-                code: KeyCode::Char('k'),
-                ..
-            }) => {
-                self.move_cursor_up(1);
+            return;
+        }
+        //* and # search for the word under the cursor, g* and g# also find
+        //it inside other words
+        let word_search = match keys {
+            "*" => Some((true, true)),
+            "#" => Some((false, true)),
+            "g*" => Some((true, false)),
+            "g#" => Some((false, false)),
+            _ => None,
+        };
+        if let Some((forward, whole)) = word_search {
+            self.pending_keys.clear();
+            self.selected_register = None;
+            self.search_word(forward, whole, count);
+            return;
+        }
+        match Motion::parse(keys, given_count) {
+            Parse::Complete(motion) => {
+                self.pending_keys.clear();
+                self.selected_register = None;
+                if let Some(motion) = self.resolve_char_search(motion) {
+                    self.move_cursor_to(motion, count);
+                }
+                return;
             }
-            Event::Key(KeyEvent {
-                code: KeyCode::Char('l'),
-                ..
-            }) => {
-                self.move_cursor_right(1, true);
+            Parse::Pending => return,
+            //Keys that started a motion are dropped along with it
+            Parse::Invalid if keys.chars().count() > 1 => {
+                self.pending_keys.clear();
+                self.selected_register = None;
+                return;
             }
-            Event::Key(KeyEvent {
-                code: KeyCode::Char('i'),
-                ..
-            }) => {
-                self.mode = Mode::Insert;
+            Parse::Invalid => self.pending_keys.clear(),
+        }
+        if let Some(operator) = Operator::from_key(c) {
+            self.operator_count = given_count;
+            self.mode = Mode::OperatorPending(operator);
+            return;
+        }
+        match c {
+            ':' => {
+                self.mode = Mode::Command;
+                self.command_buffer = String::new();
+                self.command_buffer.push(':');
             }
-            Event::Key(KeyEvent {
-                code: KeyCode::Char('a'),
-                ..
-            }) => {
+            '/' | '?' => self.start_search(c == '/'),
+            'i' => self.start_insert(count, None),
+            'a' => {
                 self.move_cursor_right(1, false);
-                self.mode = Mode::Insert;
+                self.start_insert(count, None);
             }
-            Event::Key(KeyEvent {
-                code: KeyCode::Char('A'),
-                ..
-            }) => {
-                self.move_cursor_right(self.buffer[self.y()].len(), false);
-                self.mode = Mode::Insert;
+            'A' => {
+                self.move_cursor_right(self.buffer.line(self.y()).len(), false);
+                self.start_insert(count, None);
             }
-            Event::Key(KeyEvent {
-                code: KeyCode::Char('o'),
-                ..
-            }) => {
-                self.buffer.insert(self.y() + 1, Line::new(""));
-                self.cursor.x = 0;
-                self.cursor.y = self.y() + 1;
-                self.mode = Mode::Insert;
+            'o' => self.start_insert(count, Some(OpenLine::Below)),
+            'O' => self.start_insert(count, Some(OpenLine::Above)),
+            //x is dl
+            'x' if !self.buffer.line(self.y()).is_empty() => {
+                if let Some(range) = self.motion_range(Motion::Right, count, Operator::Delete) {
+                    self.apply_operator(Operator::Delete, range);
+                }
             }
-            Event::Key(KeyEvent {
-                code: KeyCode::Char('O'),
-                ..
-            }) => {
-                self.buffer.insert(self.y(), Line::new(""));
-                self.cursor.x = 0;
-                self.mode = Mode::Insert;
+            'p' | 'P' => {
+                let name = self.selected_register.unwrap_or('"');
+                self.put(name, count, c == 'P');
             }
-            Event::Key(KeyEvent {
-                code: KeyCode::Char('0'),
-                ..
-            }) => {
-                self.cursor.x = 0;
+            'v' => self.start_visual(Mode::Visual),
+            'V' => self.start_visual(Mode::VisualLine),
+            'J' => {
+                let end = min(
+                    self.y().saturating_add(max(count, 2) - 1),
+                    self.buffer.len() - 1,
+                );
+                self.join_lines(self.y(), end);
             }
-            Event::Key(KeyEvent {
-                code: KeyCode::Char('$'),
-                ..
-            }) => {
-                self.cursor.x = self.buffer[self.y()].len() - 1;
+            'u' => self.undo(count),
+            '.' => self.repeat_change(given_count),
+            _ => {}
+        }
+        self.selected_register = None;
+    }
+
+    //"x selects the register of the next command, keeping the count typed
+    //before it
+    pub(super) fn select_register(&mut self) {
+        let (_, keys) = split_count(&self.pending_keys);
+        let n = self.pending_keys.len() - keys.len();
+        match keys.chars().nth(1) {
+            None => {}
+            Some(name) if is_register_name(name) => {
+                self.selected_register = Some(name);
+                self.pending_keys.truncate(n);
             }
+            Some(_) => {
+                self.pending_keys.clear();
+                self.selected_register = None;
+            }
+        }
+    }
+
+    fn match_control_normal(&mut self, c: char, count: Option<usize>) {
+        match c {
+            'r' => self.redo(count.unwrap_or(1)),
+            'd' => self.scroll_half_page(true, count),
+            'u' => self.scroll_half_page(false, count),
+            'f' => self.scroll_pages(true, count.unwrap_or(1)),
+            'b' => self.scroll_pages(false, count.unwrap_or(1)),
+            'v' => self.start_visual(Mode::VisualBlock),
             _ => {}
         }
     }
@@ -0,0 +1,421 @@
+use super::motion::{split_count, Motion, MotionRange, Parse, RangeKind};
+use super::registers::{is_read_only, Register};
+use super::textobject::TextObject;
+use super::{Cursor, Editor, Line, Mode, Operator};
+use crossterm::event::{Event, KeyCode, KeyEvent};
+use std::cmp::min;
+
+//Indentation of width columns, with tabs unless expandtab is set
+fn indent_string(width: usize, tabstop: usize, expandtab: bool) -> String {
+    if expandtab {
+        " ".repeat(width)
+    } else {
+        "\t".repeat(width / tabstop) + &" ".repeat(width % tabstop)
+    }
+}
+
+impl Editor {
+    pub(super) fn match_event_operator_pending(&mut self, event: Event, operator: Operator) {
+        let c = match event {
+            Event::Key(KeyEvent {
+                code: KeyCode::Char(c),
+                ..
+            }) => c,
+            _ => {
+                self.pending_keys.clear();
+                self.selected_register = None;
+                self.mode = Mode::Normal;
+                return;
+            }
+        };
+        self.pending_keys.push(c);
+        let (count, keys) = split_count(&self.pending_keys);
+        if keys.is_empty() {
+            return;
+        }
+        //The counts before and after the operator multiply, 2d3w is d6w
+        let count = match (self.operator_count, count) {
+            (None, None) => None,
+            (before, after) => Some(before.unwrap_or(1).saturating_mul(after.unwrap_or(1))),
+        };
+        let motion = Motion::parse(keys, count);
+        let count = count.unwrap_or(1);
+        //Doubling the operator, like dd or >>, acts on count lines
+        let range = if keys == operator.key().to_string() {
+            let cursor = Cursor {
+                x: self.x(true),
+                y: self.y(),
+            };
+            let end = Cursor {
+                x: cursor.x,
+                y: min(cursor.y.saturating_add(count - 1), self.buffer.len() - 1),
+            };
+            Some(MotionRange {
+                start: cursor,
+                end,
+                kind: RangeKind::Linewise,
+            })
+        } else {
+            match motion {
+                Parse::Complete(motion) => self
+                    .resolve_char_search(motion)
+                    .and_then(|motion| self.motion_range(motion, count, operator)),
+                Parse::Pending => return,
+                Parse::Invalid => match TextObject::parse(keys) {
+                    Parse::Complete(object) => self.text_object_range(object, count),
+                    Parse::Pending => return,
+                    Parse::Invalid => None,
+                },
+            }
+        };
+        self.pending_keys.clear();
+        self.mode = Mode::Normal;
+        if let Some(range) = range {
+            self.apply_operator(operator, range);
+        }
+        self.selected_register = None;
+    }
+
+    //False after telling the user if the selected register can't be written
+    pub(super) fn check_selected_register(&mut self) -> bool {
+        match self.selected_register.filter(|&name| is_read_only(name)) {
+            Some(name) => {
+                self.selected_register = None;
+                self.status_message = format!("Invalid register name: {}", name);
+                false
+            }
+            None => true,
+        }
+    }
+
+    pub(super) fn apply_operator(&mut self, operator: Operator, range: MotionRange) {
+        if !self.check_selected_register() {
+            return;
+        }
+        match operator {
+            Operator::Delete => {
+                self.yank_range(range, true);
+                self.delete_range(range);
+            }
+            Operator::Change => {
+                self.yank_range(range, true);
+                if range.kind == RangeKind::Linewise {
+                    let n = range.end.y - range.start.y + 1;
+                    self.replace_lines(range.start.y, n, vec![Line::new("")]);
+                    self.cursor = Cursor {
+                        x: 0,
+                        y: range.start.y,
+                    };
+                } else {
+                    self.delete_range(range);
+                }
+                self.start_insert(1, None);
+            }
+            Operator::Yank => {
+                self.yank_range(range, false);
+                self.cursor.y = range.start.y;
+                if range.kind == RangeKind::Charwise {
+                    self.cursor.x = range.start.x;
+                }
+            }
+            Operator::ShiftRight | Operator::ShiftLeft => {
+                self.shift_lines(range.start.y, range.end.y, operator == Operator::ShiftRight)
+            }
+        }
+    }
+
+    //The text in range, with lines joined by '\n'
+    fn range_text(&self, range: MotionRange) -> String {
+        let (start, end) = (range.start, range.end);
+        if range.kind == RangeKind::Linewise {
+            return (start.y..=end.y)
+                .map(|y| self.buffer.line(y).get_content())
+                .collect::<Vec<String>>()
+                .join("\n");
+        }
+        if start.y == end.y {
+            return self.buffer.line(start.y).slice(start.x, end.x).to_owned();
+        }
+        let first = self.buffer.line(start.y);
+        let mut text = first.slice(start.x, first.len()).to_owned();
+        for y in start.y + 1..end.y {
+            text.push('\n');
+            text.push_str(&self.buffer.line(y).get_content());
+        }
+        text.push('\n');
+        text.push_str(self.buffer.line(end.y).slice(0, end.x));
+        text
+    }
+
+    //Stores the text in range in the selected register
+    fn yank_range(&mut self, range: MotionRange, delete: bool) {
+        let register = Register {
+            text: self.range_text(range),
+            linewise: range.kind == RangeKind::Linewise,
+        };
+        self.yank(register, delete);
+    }
+
+    pub(super) fn yank(&mut self, register: Register, delete: bool) {
+        let name = self.selected_register.take();
+        self.registers.store(name, register, delete);
+    }
+
+    fn delete_range(&mut self, range: MotionRange) {
+        let (start, end) = (range.start, range.end);
+        let n = end.y - start.y + 1;
+        match range.kind {
+            RangeKind::Linewise => {
+                //The buffer always keeps a line
+                let new = if n == self.buffer.len() {
+                    vec![Line::new("")]
+                } else {
+                    Vec::new()
+                };
+                self.replace_lines(start.y, n, new);
+                self.cursor.y = start.y.min(self.buffer.len() - 1);
+                self.cursor.x = self.buffer.line(self.cursor.y).indent();
+            }
+            RangeKind::Charwise => {
+                let last = self.buffer.line(end.y);
+                let mut line = self.buffer.line(start.y).slice(0, start.x).to_owned();
+                line.push_str(last.slice(end.x, last.len()));
+                self.replace_lines(start.y, n, vec![Line::new(&line)]);
+                self.cursor = start;
+            }
+        }
+    }
+
+    //Joins the lines start_y..=end_y with a space between them, leaving out
+    //their indentation. The cursor goes where the last two were joined
+    pub(super) fn join_lines(&mut self, start_y: usize, end_y: usize) {
+        if start_y >= end_y {
+            return;
+        }
+        let mut joined = self.buffer.line(start_y).get_content();
+        let mut n_graphemes = 0;
+        for y in start_y + 1..=end_y {
+            let line = self.buffer.line(y).get_content();
+            let line = line.trim_start_matches([' ', '\t']);
+            n_graphemes = Line::new(&joined).graphemes().count();
+            if !joined.is_empty()
+                && !line.is_empty()
+                && !joined.ends_with([' ', '\t'])
+                && !line.starts_with(')')
+            {
+                joined.push(' ');
+            }
+            joined.push_str(line);
+        }
+        self.replace_lines(start_y, end_y - start_y + 1, vec![Line::new(&joined)]);
+        let line = self.buffer.line(start_y);
+        self.cursor = Cursor {
+            x: line
+                .graphemes()
+                .nth(n_graphemes)
+                .map_or(line.len(), |(x, _)| x),
+            y: start_y,
+        };
+    }
+
+    //Adds or removes a shiftwidth of indentation to the lines start_y..=end_y
+    pub(super) fn shift_lines(&mut self, start_y: usize, end_y: usize, right: bool) {
+        let options = self.buffer.options();
+        let (shiftwidth, tabstop) = (options.shiftwidth(), options.tabstop);
+        let expandtab = options.expandtab;
+        for y in start_y..=end_y {
+            let line = self.buffer.line(y);
+            if line.is_empty() {
+                continue;
+            }
+            let indent = line.indent();
+            let width = if right {
+                indent + shiftwidth
+            } else {
+                indent.saturating_sub(shiftwidth)
+            };
+            let shifted = indent_string(width, tabstop, expandtab) + line.slice(indent, line.len());
+            if shifted != line.get_content() {
+                self.set_line(y, Line::new(&shifted));
+            }
+        }
+        self.cursor.y = start_y;
+        self.cursor.x = self.buffer.line(start_y).indent();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_helpers::{editor, type_keys};
+    use super::*;
+
+    #[test]
+    fn yank_and_put() {
+        let mut editor = editor(&["foo bar", "baz"]);
+        type_keys(&mut editor, "yyjp");
+        assert_eq!(
+            editor.buffer,
+            vec![Line::new("foo bar"), Line::new("baz"), Line::new("foo bar")]
+        );
+        assert_eq!(editor.cursor, Cursor { x: 0, y: 2 });
+        type_keys(&mut editor, "w\"adwk2\"aP");
+        assert_eq!(
+            editor.buffer,
+            vec![
+                Line::new("foo bar"),
+                Line::new("babarbarz"),
+                Line::new("foo ")
+            ]
+        );
+        assert_eq!(editor.cursor, Cursor { x: 7, y: 1 });
+        type_keys(&mut editor, "x\"_ddp");
+        assert_eq!(
+            editor.buffer,
+            vec![Line::new("foo bar"), Line::new("froo ")]
+        );
+        type_keys(&mut editor, "\"%p");
+        assert_eq!(editor.status_message, "Nothing in register %");
+    }
+
+    #[test]
+    fn put_lines_of_text() {
+        let mut editor = editor(&["ab"]);
+        editor.registers.store(
+            None,
+            Register {
+                text: String::from("1\n2"),
+                linewise: false,
+            },
+            false,
+        );
+        type_keys(&mut editor, "p");
+        assert_eq!(editor.buffer, vec![Line::new("a1"), Line::new("2b")]);
+        assert_eq!(editor.cursor, Cursor { x: 1, y: 0 });
+    }
+
+    #[test]
+    fn delete_word() {
+        let mut editor = editor(&["foo bar", "baz"]);
+        type_keys(&mut editor, "dw");
+        assert_eq!(editor.buffer, vec![Line::new("bar"), Line::new("baz")]);
+        assert_eq!(editor.register('"').unwrap().text, "foo ");
+        type_keys(&mut editor, "dw");
+        assert_eq!(editor.buffer, vec![Line::new(""), Line::new("baz")]);
+        assert!(editor.mode == Mode::Normal);
+    }
+
+    #[test]
+    fn change_word() {
+        let mut editor = editor(&["foo bar"]);
+        type_keys(&mut editor, "cw");
+        assert_eq!(editor.buffer, vec![Line::new(" bar")]);
+        assert!(editor.mode == Mode::Insert);
+    }
+
+    #[test]
+    fn change_text_object() {
+        let mut editor = editor(&["f(a, b) + c", "x"]);
+        type_keys(&mut editor, "fbci(");
+        assert_eq!(editor.buffer, vec![Line::new("f() + c"), Line::new("x")]);
+        assert!(editor.mode == Mode::Insert);
+    }
+
+    #[test]
+    fn delete_text_objects() {
+        let mut editor = editor(&["foo bar baz"]);
+        type_keys(&mut editor, "wdaw");
+        assert_eq!(editor.buffer, vec![Line::new("foo baz")]);
+        type_keys(&mut editor, "dip");
+        assert_eq!(editor.buffer, vec![Line::new("")]);
+    }
+
+    #[test]
+    fn delete_to_end_and_find() {
+        let mut editor = editor(&["año, más"]);
+        type_keys(&mut editor, "dfñ");
+        assert_eq!(editor.buffer, vec![Line::new("o, más")]);
+        type_keys(&mut editor, "ld$");
+        assert_eq!(editor.buffer, vec![Line::new("o")]);
+        assert_eq!(editor.register('"').unwrap().text, ", más");
+    }
+
+    #[test]
+    fn delete_lines() {
+        let mut editor = editor(&["a", "b", "c"]);
+        type_keys(&mut editor, "jdk");
+        assert_eq!(editor.buffer, vec![Line::new("c")]);
+        assert!(editor.register('"').unwrap().linewise);
+        type_keys(&mut editor, "dd");
+        assert_eq!(editor.buffer, vec![Line::new("")]);
+    }
+
+    #[test]
+    fn delete_across_lines() {
+        let mut editor = editor(&["foo bar", "baz"]);
+        editor.cursor.x = 4;
+        type_keys(&mut editor, "de");
+        assert_eq!(editor.buffer, vec![Line::new("foo "), Line::new("baz")]);
+        type_keys(&mut editor, "0de");
+        type_keys(&mut editor, "de");
+        assert_eq!(editor.buffer, vec![Line::new("")]);
+        assert_eq!(editor.register('"').unwrap().text, " \nbaz");
+    }
+
+    #[test]
+    fn yank_does_not_change_buffer() {
+        let mut editor = editor(&["foo bar"]);
+        editor.cursor.x = 4;
+        type_keys(&mut editor, "yb");
+        assert_eq!(editor.register('"').unwrap().text, "foo ");
+        assert_eq!(editor.cursor.x, 0);
+        assert_eq!(editor.buffer, vec![Line::new("foo bar")]);
+    }
+
+    #[test]
+    fn shift_lines() {
+        let mut editor = editor(&["a", "", "  b"]);
+        editor.buffer.options_mut().shiftwidth = 2;
+        type_keys(&mut editor, ">G");
+        assert_eq!(
+            editor.buffer,
+            vec![Line::new("  a"), Line::new(""), Line::new("\tb")]
+        );
+        type_keys(&mut editor, "<<");
+        assert_eq!(editor.buffer.line(0).get_content(), "a");
+    }
+
+    #[test]
+    fn cancel_operator() {
+        let mut editor = editor(&["foo"]);
+        type_keys(&mut editor, "dz");
+        assert!(editor.mode == Mode::Normal);
+        type_keys(&mut editor, "z");
+        assert_eq!(editor.buffer, vec![Line::new("foo")]);
+    }
+
+    #[test]
+    fn counted_operators() {
+        let mut editor = editor(&["a b c d ee f g", "h", "i", "j"]);
+        type_keys(&mut editor, "2d2w");
+        assert_eq!(editor.buffer.line(0).get_content(), "ee f g");
+        type_keys(&mut editor, "d2e");
+        assert_eq!(editor.buffer.line(0).get_content(), " g");
+        type_keys(&mut editor, "j2dd");
+        assert_eq!(editor.buffer, vec![Line::new(" g"), Line::new("j")]);
+        type_keys(&mut editor, "5yy");
+        assert_eq!(editor.register('"').unwrap().text, "j");
+        type_keys(&mut editor, "k3x");
+        assert_eq!(editor.buffer, vec![Line::new(""), Line::new("j")]);
+    }
+
+    #[test]
+    fn huge_counts() {
+        let count = usize::MAX.to_string();
+        let mut editor = editor(&["a b", "c", "d"]);
+        type_keys(&mut editor, &format!("{}d{}j", count, count));
+        assert_eq!(editor.buffer, vec![Line::new("")]);
+        type_keys(&mut editor, &format!("u{}yy{}$gg{}J", count, count, count));
+        assert_eq!(editor.register('"').unwrap().text, "a b\nc\nd");
+        assert_eq!(editor.buffer, vec![Line::new("a b c d")]);
+    }
+}
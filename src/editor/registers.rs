@@ -0,0 +1,276 @@
+use super::clipboard::Clipboard;
+use super::{Cursor, Editor, Line};
+
+//Text stored by yanks and deletes. Linewise text is made of whole lines,
+//joined by '\n' but without a final one
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Register {
+    pub text: String,
+    pub linewise: bool,
+}
+
+//Names that can follow ", in the order :registers lists them
+const NAMES: &str = "\"+*0123456789abcdefghijklmnopqrstuvwxyz-.:%/";
+
+pub fn is_register_name(name: char) -> bool {
+    name == '_' || NAMES.contains(name.to_ascii_lowercase())
+}
+
+pub fn is_read_only(name: char) -> bool {
+    ".:%/".contains(name)
+}
+
+//None for a register with nothing in it
+fn non_empty(register: Register) -> Option<Register> {
+    if register.text.is_empty() && !register.linewise {
+        None
+    } else {
+        Some(register)
+    }
+}
+
+#[derive(Default)]
+pub struct Registers {
+    unnamed: Register,
+    //0 has the last yank and 1-9 the last deletes of lines, newest first
+    numbered: [Register; 10],
+    //The last delete within a line
+    small_delete: Register,
+    named: [Register; 26],
+    //The text typed in the last Insert mode and the last command line
+    pub last_insert: String,
+    pub last_command: String,
+    clipboard: Clipboard,
+}
+
+impl Registers {
+    //Stores a yank or a delete in the register name, or where vim would if
+    //there is none. The unnamed register always ends up with a copy,
+    //unless name is _
+    pub fn store(&mut self, name: Option<char>, register: Register, delete: bool) {
+        let stored = match name {
+            Some(name) if name != '"' => match self.write(name, register) {
+                Some(stored) => stored,
+                None => return,
+            },
+            _ => {
+                if !delete {
+                    self.numbered[0] = register.clone();
+                } else if register.linewise || register.text.contains('\n') {
+                    self.numbered[1..].rotate_right(1);
+                    self.numbered[1] = register.clone();
+                } else {
+                    self.small_delete = register.clone();
+                }
+                register
+            }
+        };
+        self.unnamed = stored;
+    }
+
+    //Writes the register name, returning what it has then. Uppercase names
+    //append to the lowercase register. Nothing is written to _ or to the
+    //read-only registers
+    pub fn write(&mut self, name: char, register: Register) -> Option<Register> {
+        match name {
+            'a'..='z' => {
+                self.named[name as usize - 'a' as usize] = register.clone();
+                Some(register)
+            }
+            'A'..='Z' => {
+                let named = &mut self.named[name as usize - 'A' as usize];
+                if named.text.is_empty() && !named.linewise {
+                    *named = register;
+                } else if named.linewise || register.linewise {
+                    named.text = format!("{}\n{}", named.text, register.text);
+                    named.linewise = true;
+                } else {
+                    named.text.push_str(&register.text);
+                }
+                Some(named.clone())
+            }
+            '0'..='9' => {
+                self.numbered[name as usize - '0' as usize] = register.clone();
+                Some(register)
+            }
+            '-' => {
+                self.small_delete = register.clone();
+                Some(register)
+            }
+            '+' | '*' => {
+                self.clipboard.copy(&register, name == '*');
+                Some(register)
+            }
+            _ => None,
+        }
+    }
+
+    fn get(&self, name: char) -> Register {
+        let text = |text: &String| Register {
+            text: text.clone(),
+            linewise: false,
+        };
+        match name {
+            '0'..='9' => self.numbered[name as usize - '0' as usize].clone(),
+            'a'..='z' => self.named[name as usize - 'a' as usize].clone(),
+            'A'..='Z' => self.named[name as usize - 'A' as usize].clone(),
+            '-' => self.small_delete.clone(),
+            '+' | '*' => self.clipboard.paste(name == '*'),
+            '.' => text(&self.last_insert),
+            ':' => text(&self.last_command),
+            '"' => self.unnamed.clone(),
+            _ => Register::default(),
+        }
+    }
+}
+
+impl Editor {
+    //Contents of the register name, None if it's empty
+    pub(super) fn register(&self, name: char) -> Option<Register> {
+        let register = match name {
+            '%' => Register {
+                text: self.file_name.clone(),
+                linewise: false,
+            },
+            '/' => Register {
+                text: self
+                    .last_search
+                    .as_ref()
+                    .map_or(String::new(), |search| search.pattern.clone()),
+                linewise: false,
+            },
+            _ => self.registers.get(name),
+        };
+        non_empty(register)
+    }
+
+    //Contents of the registers as shown by :registers, with line breaks as ^J
+    pub(super) fn register_list(&self) -> String {
+        NAMES
+            .chars()
+            .filter_map(|name| {
+                //The clipboard isn't read just to list it
+                let register = match name {
+                    '+' | '*' => non_empty(self.registers.clipboard.copied(name == '*').clone())?,
+                    name => self.register(name)?,
+                };
+                let mut text = register.text.replace('\n', "^J");
+                if register.linewise {
+                    text.push_str("^J");
+                }
+                Some(format!("\"{} {}", name, text))
+            })
+            .collect::<Vec<String>>()
+            .join("  ")
+    }
+
+    //Puts the register count times after the cursor, or before it with P.
+    //Linewise text goes below or above the cursor line
+    pub(super) fn put(&mut self, name: char, count: usize, before: bool) {
+        let register = match self.register(name) {
+            Some(register) => register,
+            None => {
+                self.status_message = format!("Nothing in register {}", name);
+                return;
+            }
+        };
+        let y = self.y();
+        if register.linewise {
+            let lines: Vec<Line> = (0..count)
+                .flat_map(|_| register.text.split('\n'))
+                .map(Line::new)
+                .collect();
+            let y = if before { y } else { y + 1 };
+            self.replace_lines(y, 0, lines);
+            self.cursor = Cursor {
+                x: self.buffer.line(y).indent(),
+                y,
+            };
+            return;
+        }
+        let line = self.buffer.line(y);
+        let x = match self.x(true) {
+            x if before || line.is_empty() => x,
+            x => line.next_valid_index(x).unwrap_or_else(|| line.len()),
+        };
+        let head = line.slice(0, x).to_owned() + &register.text.repeat(count);
+        let mut lines: Vec<String> = head.split('\n').map(String::from).collect();
+        //The cursor ends on the last character put, or at the start of the
+        //text if it has several lines
+        let n_graphemes = Line::new(&lines[0]).graphemes().count();
+        if let Some(last) = lines.last_mut() {
+            last.push_str(line.slice(x, line.len()));
+        }
+        let multiline = lines.len() > 1;
+        self.replace_lines(y, 1, lines.iter().map(|line| Line::new(line)).collect());
+        let x = match multiline {
+            true => x,
+            false => self
+                .buffer
+                .line(y)
+                .graphemes()
+                .nth(n_graphemes.saturating_sub(1))
+                .map_or(0, |(x, _)| x),
+        };
+        self.cursor = Cursor { x, y };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Register {
+        Register {
+            text: String::from(text),
+            linewise: false,
+        }
+    }
+
+    fn lines(text: &str) -> Register {
+        Register {
+            text: String::from(text),
+            linewise: true,
+        }
+    }
+
+    #[test]
+    fn numbered_registers() {
+        let mut registers = Registers::default();
+        registers.store(None, lines("a"), true);
+        registers.store(None, lines("b"), true);
+        registers.store(None, text("c"), true);
+        registers.store(None, text("d"), false);
+        assert_eq!(registers.get('1'), lines("b"));
+        assert_eq!(registers.get('2'), lines("a"));
+        assert_eq!(registers.get('-'), text("c"));
+        assert_eq!(registers.get('0'), text("d"));
+        assert_eq!(registers.get('"'), text("d"));
+    }
+
+    #[test]
+    fn named_registers() {
+        let mut registers = Registers::default();
+        registers.store(Some('a'), text("x"), false);
+        registers.store(Some('A'), text("y"), true);
+        assert_eq!(registers.get('a'), text("xy"));
+        registers.store(Some('A'), lines("z"), false);
+        assert_eq!(registers.get('a'), lines("xy\nz"));
+        assert_eq!(registers.get('"'), lines("xy\nz"));
+        registers.store(Some('_'), text("w"), true);
+        assert_eq!(registers.get('"'), lines("xy\nz"));
+        assert_eq!(registers.get('0'), Register::default());
+    }
+
+    #[test]
+    fn read_only_registers() {
+        let mut editor = Editor::new();
+        editor.file_name = String::from("a.txt");
+        editor.registers.last_command = String::from("w");
+        assert_eq!(editor.register('%'), Some(text("a.txt")));
+        assert_eq!(editor.register(':'), Some(text("w")));
+        assert_eq!(editor.register('.'), None);
+        assert!(is_read_only('%') && !is_read_only('a'));
+        assert!(is_register_name('A') && !is_register_name('!'));
+    }
+}
@@ -1,3 +1,4 @@
+use super::search::matches;
 use super::{Editor, Mode};
 use crossterm::terminal::size as term_size;
 use crossterm::{
@@ -7,15 +8,19 @@ use crossterm::{
     terminal::{Clear, ClearType},
     Result,
 };
-use std::cmp::min;
+use regex::Regex;
+use std::cmp::{max, min};
 use std::fmt::Write as fmt_write;
 use std::io;
 use std::io::prelude::*;
+use std::ops::Range;
+use unicode_width::UnicodeWidthChar;
 
 impl Editor {
     pub(super) fn refresh_screen(&mut self) -> Result<()> {
         let mut buf = String::new();
         let term_size = term_size()?;
+        self.screen_size = term_size;
         self.recalculate_scroll(term_size);
         self.draw_rows(&mut buf, term_size)?;
         self.draw_status_bar(&mut buf, term_size.0)?;
@@ -30,17 +35,33 @@ impl Editor {
         queue!(buf, MoveTo(0, 0))?;
         let mut rows_written = 0;
         let mut index = self.y_scroll;
+        let search = self.highlighted_search();
         while rows_written < n_rows - 1 && index < self.buffer.len() {
-            let mut line_part = 0;
-            while rows_written < n_rows && line_part <= self.buffer[index].len() / n_cols {
+            let line = self.buffer.line(index);
+            let starts = line.row_starts(n_cols);
+            let highlighted = self.highlighted(index, search.as_ref());
+            for (i, &start) in starts.iter().enumerate() {
+                if rows_written >= n_rows - 1 {
+                    break;
+                }
+                let end = starts.get(i + 1).copied().unwrap_or(start + n_cols);
                 queue!(buf, Clear(ClearType::CurrentLine))?;
-                write!(
-                    buf,
-                    "{}\r\n",
-                    self.buffer[index].take_substr(line_part * n_cols, n_cols)
-                )?;
+                let mut x = start;
+                for range in highlighted.iter() {
+                    let (from, to) = (max(range.start, x), min(range.end, end));
+                    if from < to {
+                        write!(buf, "{}", line.take_substr(x, from - x))?;
+                        write!(buf, "{}", line.take_substr(from, to - from).negative())?;
+                        x = to;
+                    }
+                }
+                write!(buf, "{}", line.take_substr(x, end - x))?;
+                //A highlighted line break is shown as a space
+                if i + 1 == starts.len() && highlighted.iter().any(|range| range.end > line.len()) {
+                    write!(buf, "{}", " ".negative())?;
+                }
+                write!(buf, "\r\n")?;
                 rows_written += 1;
-                line_part += 1;
             }
             index += 1;
         }
@@ -52,36 +73,60 @@ impl Editor {
         Ok(())
     }
 
+    //Display indexes of line y drawn highlighted, sorted. They go past its
+    //len if the line break is highlighted too
+    fn highlighted(&self, y: usize, search: Option<&Regex>) -> Vec<Range<usize>> {
+        let mut ranges = Vec::new();
+        if self.mode.is_visual() {
+            ranges.extend(self.selected_columns(y));
+        }
+        ranges.extend(self.confirming_match(y));
+        if let Some(regex) = search {
+            ranges.extend(matches(regex, &self.buffer.line(y)));
+            ranges.sort_by_key(|range| range.start);
+        }
+        ranges
+    }
+
     fn draw_status_bar(&self, buf: &mut String, n_cols: u16) -> Result<()> {
         let n_cols = n_cols as usize;
         queue!(buf, Clear(ClearType::CurrentLine))?;
         let mut bar = String::new();
         if self.mode == Mode::Command {
             bar = self.command_buffer.clone();
+        } else if !self.status_message.is_empty() {
+            bar = self.status_message.clone();
         } else {
             write!(bar, "{} mode ", self.mode)?;
+            if let Some((name, _)) = self.recording {
+                write!(bar, "recording @{} ", name)?;
+            }
             write!(bar, "{}", self.file_name)?;
         }
         let row = self.y() + 1; //The stored pos is 0-indexed
         let row = String::from(" ") + &row.to_string();
-        bar.truncate(n_cols - min(row.len(), n_cols));
-        while n_cols - bar.len() > row.len() {
-            write!(bar, " ")?;
-        }
+        //The bar is cut by display width, as wide characters take two columns
+        let width = n_cols - min(row.len(), n_cols);
+        let mut bar_width = 0;
+        let mut bar: String = bar
+            .chars()
+            .take_while(|c| {
+                let c_width = c.width().unwrap_or(0);
+                let fits = bar_width + c_width <= width;
+                if fits {
+                    bar_width += c_width;
+                }
+                fits
+            })
+            .collect();
+        bar.extend(std::iter::repeat_n(' ', width - bar_width));
         write!(bar, "{}", row)?;
         write!(buf, "{}", bar.negative())?;
         Ok(())
     }
 
     fn reposition_cursor(&self, buf: &mut String, n_cols: u16) -> Result<()> {
-        let cursor_screen_pos = self.cursor_pos_to_screen_pos(
-            n_cols,
-            if self.mode == Mode::Insert {
-                false
-            } else {
-                true
-            },
-        );
+        let cursor_screen_pos = self.cursor_pos_to_screen_pos(n_cols, self.mode != Mode::Insert);
         queue!(buf, MoveTo(cursor_screen_pos.0, cursor_screen_pos.1))?;
         Ok(())
     }
@@ -99,4 +144,15 @@ mod tests {
         let mut s = String::new();
         editor.draw_status_bar(&mut s, 0)
     }
+
+    #[test]
+    fn status_bar_truncated_by_width() -> Result<()> {
+        let mut editor = Editor::new();
+        editor.buffer.push(Line::new(""));
+        editor.status_message = String::from("界界界");
+        let mut s = String::new();
+        editor.draw_status_bar(&mut s, 5)?;
+        assert!(s.contains("界  1"));
+        Ok(())
+    }
 }
@@ -0,0 +1,504 @@
+use super::motion::Motion;
+use super::{Cursor, Editor, Line, Mode};
+use regex::{Regex, RegexBuilder};
+use std::ops::Range;
+
+//A pattern searched for with / or ?, forward or backward
+#[derive(Debug, Clone, PartialEq)]
+pub struct Search {
+    pub pattern: String,
+    pub forward: bool,
+}
+
+//Builds the regex of a pattern, in the syntax of the regex crate, where \<
+//and \> are the start and end of a word. Case is ignored with ignorecase,
+//unless smartcase is set and the pattern has uppercase letters. \c and \C
+//anywhere in the pattern force ignoring or matching case
+pub fn compile(pattern: &str, ignorecase: bool, smartcase: bool) -> Result<Regex, String> {
+    let mut source = String::new();
+    let mut has_uppercase = false;
+    let mut ignore_case = None;
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            has_uppercase |= c.is_uppercase();
+            source.push(c);
+            continue;
+        }
+        //Escaped letters like \S are classes, not uppercase letters
+        match chars.next() {
+            Some('c') => ignore_case = Some(true),
+            Some('C') => ignore_case = Some(false),
+            Some(escaped) => {
+                source.push(c);
+                source.push(escaped);
+            }
+            None => source.push(c),
+        }
+    }
+    let ignore_case = ignore_case.unwrap_or(ignorecase && !(smartcase && has_uppercase));
+    RegexBuilder::new(&source)
+        .case_insensitive(ignore_case)
+        .build()
+        .map_err(|_| format!("Invalid pattern: {}", pattern))
+}
+
+//Display indexes the cursor goes to for each match in line, overlapping
+//ones included. A match at the end of the line is on its last grapheme
+fn match_starts(regex: &Regex, line: &Line) -> Vec<usize> {
+    let text = line.get_content();
+    let last = line.prev_valid_index(line.len()).unwrap_or(0);
+    let mut found_starts = Vec::new();
+    let mut start = 0;
+    //find_at sees the text before start, so anchors and word boundaries
+    //work as if the whole line was searched
+    while let Some(found) = regex.find_at(&text, start) {
+        found_starts.push(found.start()..found.start());
+        match text[found.start()..].chars().next() {
+            Some(c) => start = found.start() + c.len_utf8(),
+            None => break,
+        }
+    }
+    let mut starts: Vec<usize> = line
+        .display_ranges(&found_starts)
+        .into_iter()
+        .map(|range| range.start.min(last))
+        .collect();
+    starts.dedup();
+    starts
+}
+
+//Display ranges of the matches in line that don't overlap, the ones
+//highlighted. Empty matches are left out
+pub fn matches(regex: &Regex, line: &Line) -> Vec<Range<usize>> {
+    let ranges: Vec<Range<usize>> = regex
+        .find_iter(&line.get_content())
+        .filter(|found| !found.is_empty())
+        .map(|found| found.range())
+        .collect();
+    line.display_ranges(&ranges)
+}
+
+impl Editor {
+    //Opens the command line to type a pattern after / or ?
+    pub(super) fn start_search(&mut self, forward: bool) {
+        self.mode = Mode::Command;
+        self.command_buffer = String::from(if forward { "/" } else { "?" });
+        self.search_start = self.cursor;
+        self.search_history_index = self.search_history.len();
+    }
+
+    //The search being typed in the command line, if it's a search
+    pub(super) fn typed_search(&self) -> Option<Search> {
+        if self.mode != Mode::Command {
+            return None;
+        }
+        let mut chars = self.command_buffer.chars();
+        let forward = match chars.next() {
+            Some('/') => true,
+            Some('?') => false,
+            _ => return None,
+        };
+        Some(Search {
+            pattern: chars.as_str().to_owned(),
+            forward,
+        })
+    }
+
+    pub(super) fn search_regex(&self, pattern: &str) -> Result<Regex, String> {
+        let options = self.buffer.options();
+        compile(pattern, options.ignorecase, options.smartcase)
+    }
+
+    //Moves the cursor to the match of the pattern being typed, or back to
+    //where the search started if there is none or it isn't complete yet
+    pub(super) fn preview_search(&mut self) {
+        let search = match self.typed_search() {
+            Some(search) => search,
+            None => return,
+        };
+        self.cursor = self.search_start;
+        if search.pattern.is_empty() {
+            return;
+        }
+        if let Ok(regex) = self.search_regex(&search.pattern) {
+            if let Some((target, _)) = self.search_target(&regex, search.forward, 1) {
+                self.cursor = target;
+            }
+        }
+    }
+
+    pub(super) fn cancel_search(&mut self) {
+        self.cursor = self.search_start;
+    }
+
+    //Replaces the pattern being typed with an older or newer one from the
+    //search history
+    pub(super) fn browse_search_history(&mut self, older: bool) {
+        let index = if older {
+            match self.search_history_index.checked_sub(1) {
+                Some(index) => index,
+                None => return,
+            }
+        } else if self.search_history_index < self.search_history.len() {
+            self.search_history_index + 1
+        } else {
+            return;
+        };
+        self.search_history_index = index;
+        self.command_buffer.truncate(1);
+        if let Some(pattern) = self.search_history.get(index) {
+            self.command_buffer.push_str(pattern);
+        }
+        self.preview_search();
+    }
+
+    //Searches what was typed after / or ?. An empty pattern searches the
+    //last one again
+    pub(super) fn run_search(&mut self, mut search: Search) {
+        self.cursor = self.search_start;
+        if search.pattern.is_empty() {
+            match &self.last_search {
+                Some(last) => search.pattern = last.pattern.clone(),
+                None => {
+                    self.status_message = String::from("No previous regular expression");
+                    return;
+                }
+            }
+        }
+        self.add_to_search_history(&search.pattern);
+        if let Err(message) = self.search_regex(&search.pattern) {
+            self.status_message = message;
+            return;
+        }
+        self.last_search = Some(search);
+        self.move_cursor_to(Motion::SearchNext { reverse: false }, 1);
+    }
+
+    pub(super) fn add_to_search_history(&mut self, pattern: &str) {
+        self.search_history.retain(|old| old != pattern);
+        self.search_history.push(pattern.to_owned());
+    }
+
+    //Searches for the word under the cursor, or the first one after it in
+    //its line, preferring words of letters, digits and underscores over runs
+    //of punctuation. With whole set, only whole words match
+    pub(super) fn search_word(&mut self, forward: bool, whole: bool, count: usize) {
+        let (x, y) = (self.x(true), self.y());
+        let line = self.buffer.line(y);
+        let is_keyword = |range: &&Range<usize>| {
+            line.slice(range.start, range.end)
+                .starts_with(|c: char| c.is_alphanumeric() || c == '_')
+        };
+        let words = line.words(false);
+        let mut after = words.iter().filter(|range| range.end > x);
+        let word = match after.clone().find(is_keyword).or_else(|| after.next()) {
+            Some(word) => word.clone(),
+            None => {
+                self.status_message = String::from("No string under cursor");
+                return;
+            }
+        };
+        let text = line.slice(word.start, word.end);
+        let mut pattern = regex::escape(text);
+        if whole && is_keyword(&&word) {
+            pattern = format!("\\<{}\\>", pattern);
+        }
+        //Like in vim, ignorecase is used but not smartcase
+        if self.buffer.options().ignorecase {
+            pattern.push_str("\\c");
+        }
+        //Backward searches start at the word, so they don't find it
+        self.cursor.x = word.start;
+        self.add_to_search_history(&pattern);
+        self.last_search = Some(Search { pattern, forward });
+        self.move_cursor_to(Motion::SearchNext { reverse: false }, count);
+    }
+
+    //Where the search repeated count times takes the cursor and whether it
+    //wrapped around the end of the buffer, None if there are no matches
+    fn search_target(&self, regex: &Regex, forward: bool, count: usize) -> Option<(Cursor, bool)> {
+        let mut pos = Cursor {
+            x: self.x(true),
+            y: self.y(),
+        };
+        let mut wrapped = false;
+        for _ in 0..count {
+            let (next, wrap) = self.next_match(regex, forward, pos)?;
+            pos = next;
+            wrapped |= wrap;
+        }
+        Some((pos, wrapped))
+    }
+
+    //The first match after pos in the direction of the search. Lines are
+    //looked at in order, ending with the one of pos again
+    fn next_match(&self, regex: &Regex, forward: bool, pos: Cursor) -> Option<(Cursor, bool)> {
+        let n_lines = self.buffer.len();
+        for step in 0..=n_lines {
+            let (y, wrapped) = if forward {
+                ((pos.y + step) % n_lines, pos.y + step >= n_lines)
+            } else {
+                ((pos.y + n_lines - step) % n_lines, step > pos.y)
+            };
+            let starts = match_starts(regex, &self.buffer.line(y));
+            let x = match (step, forward) {
+                (0, true) => starts.into_iter().find(|&x| x > pos.x),
+                (0, false) => starts.into_iter().rev().find(|&x| x < pos.x),
+                (_, true) => starts.first().copied(),
+                (_, false) => starts.last().copied(),
+            };
+            if let Some(x) = x {
+                return Some((Cursor { x, y }, wrapped));
+            }
+        }
+        None
+    }
+
+    //Target of n, or of N if reverse, without side effects
+    pub(super) fn search_next_target(&self, reverse: bool, count: usize) -> Option<Cursor> {
+        let search = self.last_search.as_ref()?;
+        let regex = self.search_regex(&search.pattern).ok()?;
+        self.search_target(&regex, search.forward ^ reverse, count)
+            .map(|(target, _)| target)
+    }
+
+    //Target of n or N, telling when the search wraps around or finds nothing.
+    //Matches are highlighted again after :noh
+    pub(super) fn search_next(&mut self, reverse: bool, count: usize) -> Option<Cursor> {
+        let search = match self.last_search.clone() {
+            Some(search) => search,
+            None => {
+                self.status_message = String::from("No previous regular expression");
+                return None;
+            }
+        };
+        let regex = match self.search_regex(&search.pattern) {
+            Ok(regex) => regex,
+            Err(message) => {
+                self.status_message = message;
+                return None;
+            }
+        };
+        let forward = search.forward ^ reverse;
+        self.highlight_search = true;
+        match self.search_target(&regex, forward, count) {
+            Some((target, wrapped)) => {
+                if wrapped && forward {
+                    self.status_message = String::from("search hit BOTTOM, continuing at TOP");
+                } else if wrapped {
+                    self.status_message = String::from("search hit TOP, continuing at BOTTOM");
+                }
+                Some(target)
+            }
+            None => {
+                self.status_message = format!("Pattern not found: {}", search.pattern);
+                None
+            }
+        }
+    }
+
+    //The regex whose matches are drawn highlighted, the one of the pattern
+    //being typed or of the last search until :noh
+    pub(super) fn highlighted_search(&self) -> Option<Regex> {
+        let search = match self.typed_search() {
+            Some(search) => search,
+            None if self.highlight_search => self.last_search.clone()?,
+            None => return None,
+        };
+        self.search_regex(&search.pattern).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_helpers::{editor, type_keys};
+    use super::*;
+
+    #[test]
+    fn search_and_wrap_around() {
+        let mut editor = editor(&["foo bar", "\tbar", "foo"]);
+        type_keys(&mut editor, "/bar\n");
+        assert_eq!(editor.cursor, Cursor { x: 4, y: 0 });
+        type_keys(&mut editor, "n");
+        assert_eq!(editor.cursor, Cursor { x: 4, y: 1 });
+        assert_eq!(editor.status_message, "");
+        type_keys(&mut editor, "n");
+        assert_eq!(editor.cursor, Cursor { x: 4, y: 0 });
+        assert_eq!(
+            editor.status_message,
+            "search hit BOTTOM, continuing at TOP"
+        );
+        type_keys(&mut editor, "N");
+        assert_eq!(editor.cursor, Cursor { x: 4, y: 1 });
+        assert_eq!(
+            editor.status_message,
+            "search hit TOP, continuing at BOTTOM"
+        );
+        type_keys(&mut editor, "?foo\n");
+        assert_eq!(editor.cursor, Cursor { x: 0, y: 0 });
+        type_keys(&mut editor, "2n");
+        assert_eq!(editor.cursor, Cursor { x: 0, y: 0 });
+        type_keys(&mut editor, "/\n");
+        assert_eq!(editor.cursor, Cursor { x: 0, y: 2 });
+        assert_eq!(editor.register('/').unwrap().text, "foo");
+    }
+
+    #[test]
+    fn overlapping_matches() {
+        let mut editor = editor(&["aaaa"]);
+        type_keys(&mut editor, "/aa\nn");
+        assert_eq!(editor.cursor.x, 2);
+        type_keys(&mut editor, "n");
+        assert_eq!(editor.cursor.x, 0);
+    }
+
+    #[test]
+    fn pattern_not_found() {
+        let mut editor = editor(&["foo"]);
+        type_keys(&mut editor, "n");
+        assert_eq!(editor.status_message, "No previous regular expression");
+        type_keys(&mut editor, "/x\n");
+        assert_eq!(editor.status_message, "Pattern not found: x");
+        assert_eq!(editor.cursor, Cursor { x: 0, y: 0 });
+    }
+
+    #[test]
+    fn incremental_preview() {
+        let mut editor = editor(&["ab", "ac"]);
+        type_keys(&mut editor, "/a");
+        assert_eq!(editor.cursor, Cursor { x: 0, y: 1 });
+        type_keys(&mut editor, "b");
+        assert_eq!(editor.cursor, Cursor { x: 0, y: 0 });
+        type_keys(&mut editor, "x");
+        assert_eq!(editor.cursor, Cursor { x: 0, y: 0 });
+        type_keys(&mut editor, "<Esc>");
+        assert!(editor.mode == Mode::Normal);
+        assert_eq!(editor.last_search, None);
+    }
+
+    #[test]
+    fn erase_search() {
+        let mut editor = editor(&["ab", "ac"]);
+        type_keys(&mut editor, "?c<BS>");
+        assert_eq!(editor.cursor, Cursor { x: 0, y: 0 });
+        type_keys(&mut editor, "c");
+        assert_eq!(editor.cursor, Cursor { x: 1, y: 1 });
+        type_keys(&mut editor, "<BS><BS>");
+        assert!(editor.mode == Mode::Normal);
+        assert_eq!(editor.cursor, Cursor { x: 0, y: 0 });
+        assert_eq!(editor.last_search, None);
+    }
+
+    #[test]
+    fn search_history() {
+        let mut editor = editor(&["ab", "ac"]);
+        type_keys(&mut editor, "/b\n/c\n/b\n");
+        assert_eq!(editor.search_history, vec!["c", "b"]);
+        type_keys(&mut editor, "/");
+        editor.browse_search_history(true);
+        editor.browse_search_history(true);
+        assert_eq!(editor.command_buffer, "/c");
+        assert_eq!(editor.cursor, Cursor { x: 1, y: 1 });
+        editor.browse_search_history(false);
+        editor.browse_search_history(false);
+        assert_eq!(editor.command_buffer, "/");
+    }
+
+    #[test]
+    fn highlight_until_noh() {
+        let mut editor = editor(&["añ añ", "x"]);
+        assert!(editor.highlighted_search().is_none());
+        type_keys(&mut editor, "/ñ");
+        let regex = editor.highlighted_search().unwrap();
+        assert_eq!(matches(&regex, &editor.buffer.line(0)), vec![1..2, 4..5]);
+        type_keys(&mut editor, "\n:noh\n");
+        assert!(editor.highlighted_search().is_none());
+        type_keys(&mut editor, "n");
+        assert!(editor.highlighted_search().is_some());
+    }
+
+    #[test]
+    fn regex_syntax() {
+        let line = Line::new("foo1 (bar) foobar");
+        let find = |pattern| matches(&compile(pattern, false, false).unwrap(), &line);
+        assert_eq!(find(r"[a-z]+\d"), vec![0..4]);
+        assert_eq!(find("^foo"), vec![0..3]);
+        assert_eq!(find("bar$"), vec![14..17]);
+        assert_eq!(find(r"\(bar\)|(fo)+o\b"), vec![5..10]);
+        assert_eq!(find(r"\<bar"), vec![6..9]);
+        assert_eq!(find(r"foo\>"), vec![] as Vec<Range<usize>>);
+        assert!(compile("(foo", false, false).is_err());
+    }
+
+    #[test]
+    fn ignore_case() {
+        let line = Line::new("Foo foo");
+        let find = |pattern, ignorecase, smartcase| {
+            matches(&compile(pattern, ignorecase, smartcase).unwrap(), &line)
+        };
+        assert_eq!(find("foo", false, false), vec![4..7]);
+        assert_eq!(find("foo", true, false), vec![0..3, 4..7]);
+        assert_eq!(find("Foo", true, false), vec![0..3, 4..7]);
+        assert_eq!(find("Foo", true, true), vec![0..3]);
+        assert_eq!(find("foo", true, true), vec![0..3, 4..7]);
+        assert_eq!(find(r"\Sfoo", true, true), vec![] as Vec<Range<usize>>);
+        assert_eq!(find(r"foo\c", false, false), vec![0..3, 4..7]);
+        assert_eq!(find(r"\Cfoo", true, false), vec![4..7]);
+    }
+
+    #[test]
+    fn display_indexes() {
+        let mut editor = editor(&["\t界 añb", "añb"]);
+        type_keys(&mut editor, "/a.b\n");
+        assert_eq!(editor.cursor, Cursor { x: 7, y: 0 });
+        let regex = editor.highlighted_search().unwrap();
+        assert_eq!(matches(&regex, &editor.buffer.line(0)), vec![7..10]);
+        type_keys(&mut editor, "/b$\nn");
+        assert_eq!(editor.cursor, Cursor { x: 2, y: 1 });
+        type_keys(&mut editor, ":set ic\n/A\n");
+        assert_eq!(editor.cursor, Cursor { x: 7, y: 0 });
+        type_keys(&mut editor, "/(\n");
+        assert_eq!(editor.status_message, "Invalid pattern: (");
+        assert_eq!(editor.register('/').unwrap().text, "A");
+    }
+
+    #[test]
+    fn search_word_under_cursor() {
+        let mut editor = editor(&["foo(bar)", "foobar bar", "-> bar"]);
+        type_keys(&mut editor, "fb*");
+        assert_eq!(editor.cursor, Cursor { x: 7, y: 1 });
+        assert_eq!(editor.register('/').unwrap().text, r"\<bar\>");
+        type_keys(&mut editor, "#");
+        assert_eq!(editor.cursor, Cursor { x: 4, y: 0 });
+        type_keys(&mut editor, "2g*");
+        assert_eq!(editor.cursor, Cursor { x: 7, y: 1 });
+        type_keys(&mut editor, "gg0g#");
+        assert_eq!(editor.cursor, Cursor { x: 0, y: 1 });
+        //Words of punctuation are only taken if no other follows
+        type_keys(&mut editor, "G0*");
+        assert_eq!(editor.cursor, Cursor { x: 4, y: 0 });
+        assert_eq!(editor.search_history, vec!["bar", "foo", r"\<bar\>"]);
+        assert!(editor.highlighted_search().is_some());
+    }
+
+    #[test]
+    fn search_word_ignores_smartcase() {
+        let mut editor = editor(&["Foo", "foo", "FOO"]);
+        editor.buffer.options_mut().ignorecase = true;
+        editor.buffer.options_mut().smartcase = true;
+        type_keys(&mut editor, "*");
+        assert_eq!(editor.cursor, Cursor { x: 0, y: 1 });
+        assert_eq!(editor.register('/').unwrap().text, r"\<Foo\>\c");
+        type_keys(&mut editor, "n");
+        assert_eq!(editor.cursor, Cursor { x: 0, y: 2 });
+    }
+
+    #[test]
+    fn search_unicode_word() {
+        //Unicode word boundaries keep foo.bar together
+        let mut editor = editor(&["foo.bar x", "bar foo.bar"]);
+        type_keys(&mut editor, "*");
+        assert_eq!(editor.cursor, Cursor { x: 4, y: 1 });
+    }
+}
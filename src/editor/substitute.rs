@@ -0,0 +1,660 @@
+use super::search::Search;
+use super::{Cursor, Editor, Line, Mode};
+use crossterm::event::{Event, KeyCode, KeyEvent};
+use regex::{Captures, Regex};
+use std::ops::Range;
+
+//A :s being run. Matches are looked for from the byte from of line y on,
+//up to line last, always in the text the line had before the substitution
+pub struct Substitution {
+    regex: Regex,
+    replacement: String,
+    global: bool,
+    y: usize,
+    from: usize,
+    last: usize,
+    //Whether from is right after a match, where an empty one isn't taken
+    after_match: bool,
+    //Line and bytes of the match waiting for confirmation
+    current: Option<(usize, Range<usize>)>,
+    count: usize,
+    n_lines: usize,
+    //Line y as it was, and what replaces its bytes up to copied with the
+    //substitutions made so far. They are written to the buffer once it's
+    //done with, whether it has any not written yet and whether it had any
+    text: String,
+    output: String,
+    copied: usize,
+    unwritten: bool,
+    line_changed: bool,
+    cursor: Cursor,
+}
+
+impl Substitution {
+    //Bytes that a range of line y as it was takes in the buffer, where the
+    //substitutions up to copied are already written
+    fn written(&self, range: Range<usize>) -> Range<usize> {
+        let shift = |i: usize| self.output.len() + i - self.copied;
+        shift(range.start)..shift(range.end)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Case {
+    Upper,
+    Lower,
+}
+
+//Case changes of \u, \l, \U and \L for the text added to a replacement
+#[derive(Default)]
+struct CaseChange {
+    next: Option<Case>,
+    rest: Option<Case>,
+}
+
+impl CaseChange {
+    fn push(&mut self, text: &mut String, s: &str) {
+        for c in s.chars() {
+            match self.next.take().or(self.rest) {
+                Some(Case::Upper) => text.extend(c.to_uppercase()),
+                Some(Case::Lower) => text.extend(c.to_lowercase()),
+                None => text.push(c),
+            }
+        }
+    }
+}
+
+//Part of the arguments of :s up to an unescaped delimiter, and what follows
+//it. Escaped delimiters lose their backslash, other escapes are kept
+fn split_at_delimiter(text: &str, delimiter: char) -> (String, Option<&str>) {
+    let mut part = String::new();
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == delimiter {
+            return (part, Some(&text[i + c.len_utf8()..]));
+        }
+        if c == '\\' {
+            match chars.next() {
+                Some((_, escaped)) if escaped == delimiter => part.push(escaped),
+                Some((_, escaped)) => {
+                    part.push(c);
+                    part.push(escaped);
+                }
+                None => part.push(c),
+            }
+        } else {
+            part.push(c);
+        }
+    }
+    (part, None)
+}
+
+//The arguments of :s, like /a/b/g, if command is a :s
+fn substitute_args(command: &str) -> Option<&str> {
+    let args = command
+        .strip_prefix("substitute")
+        .or_else(|| command.strip_prefix('s'))?;
+    match args.chars().next() {
+        Some(c) if !c.is_alphanumeric() && !c.is_whitespace() && !"\\\"|".contains(c) => Some(args),
+        _ => None,
+    }
+}
+
+//Replaces each ~ of a replacement with the previous one
+fn expand_tilde(replacement: &str, previous: &str) -> String {
+    let mut text = String::new();
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '~' => text.push_str(previous),
+            '\\' => {
+                text.push(c);
+                text.extend(chars.next());
+            }
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+//Text a match is replaced with. & and \0 are the whole match and \1 to \9
+//its groups. \u and \l change the case of the next character, \U and \L
+//the one of the rest until \E or \e. \r and \n break the line
+fn expand(replacement: &str, captures: &Captures) -> String {
+    let mut text = String::new();
+    let mut case = CaseChange::default();
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => case.push(&mut text, &captures[0]),
+            '\\' => match chars.next() {
+                Some(n @ '0'..='9') => {
+                    let group = captures.get(n as usize - '0' as usize);
+                    case.push(&mut text, group.map_or("", |group| group.as_str()));
+                }
+                Some('u') => case.next = Some(Case::Upper),
+                Some('l') => case.next = Some(Case::Lower),
+                Some('U') => case.rest = Some(Case::Upper),
+                Some('L') => case.rest = Some(Case::Lower),
+                Some('E') | Some('e') => case = CaseChange::default(),
+                Some('r') | Some('n') => text.push('\n'),
+                Some('t') => text.push('\t'),
+                Some(escaped) => case.push(&mut text, &escaped.to_string()),
+                None => text.push(c),
+            },
+            c => case.push(&mut text, &c.to_string()),
+        }
+    }
+    text
+}
+
+impl Editor {
+    //Runs command if it's a :s, returning whether it was
+    pub(super) fn run_substitute(&mut self, command: &str) -> bool {
+        let command = command.strip_prefix(':').unwrap_or(command);
+        let range_len = command
+            .find(|c: char| !"0123456789.$%,;+-'<> ".contains(c))
+            .unwrap_or(command.len());
+        let args = match substitute_args(&command[range_len..]) {
+            Some(args) => args,
+            None => return false,
+        };
+        match self.line_range(&command[..range_len]) {
+            Ok((first, last)) => self.substitute(first, last, args),
+            Err(message) => self.status_message = message,
+        }
+        true
+    }
+
+    //First and last lines of a range like 2,$ or '<,'>. % is every line,
+    //and an empty range the line of the cursor
+    fn line_range(&self, range: &str) -> Result<(usize, usize), String> {
+        let range = range.trim();
+        if range == "%" {
+            return Ok((0, self.buffer.len() - 1));
+        }
+        let mut addresses = Vec::new();
+        for address in range.split([',', ';']) {
+            addresses.push(self.address(address.trim())?);
+        }
+        let (first, last) = match addresses[..] {
+            [y] => (y, y),
+            [first, last] => (first.min(last), first.max(last)),
+            _ => return Err(String::from("Invalid range")),
+        };
+        Ok((first, last))
+    }
+
+    //Line of an address like 3, ., $, '< or '>, followed by +n or -n
+    fn address(&self, address: &str) -> Result<usize, String> {
+        let y = self.y() as isize;
+        let last_y = self.buffer.len() as isize - 1;
+        let mark = |first| match self.visual_lines {
+            Some((start, end)) => Ok(if first { start } else { end } as isize),
+            None => Err(String::from("Mark not set")),
+        };
+        let digits = address
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(address.len());
+        let (mut line, mut rest) = if digits > 0 {
+            let n: isize = address[..digits]
+                .parse()
+                .map_err(|_| String::from("Invalid range"))?;
+            (n.max(1) - 1, &address[digits..])
+        } else if let Some(rest) = address.strip_prefix('.') {
+            (y, rest)
+        } else if let Some(rest) = address.strip_prefix('$') {
+            (last_y, rest)
+        } else if let Some(rest) = address.strip_prefix("'<") {
+            (mark(true)?, rest)
+        } else if let Some(rest) = address.strip_prefix("'>") {
+            (mark(false)?, rest)
+        } else {
+            (y, address)
+        };
+        while let Some(sign) = rest.chars().next() {
+            let sign: isize = match sign {
+                '+' => 1,
+                '-' => -1,
+                _ => return Err(String::from("Invalid range")),
+            };
+            rest = &rest[1..];
+            let digits = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let n = match digits {
+                0 => 1,
+                _ => rest[..digits]
+                    .parse()
+                    .map_err(|_| String::from("Invalid range"))?,
+            };
+            line = sign
+                .checked_mul(n)
+                .and_then(|n| line.checked_add(n))
+                .ok_or_else(|| String::from("Invalid range"))?;
+            rest = &rest[digits..];
+        }
+        if line < 0 || line > last_y {
+            return Err(String::from("Invalid range"));
+        }
+        Ok(line as usize)
+    }
+
+    //:s/pattern/replacement/flags on the lines first..=last. The flags are
+    //g to replace every match in a line and not only the first one, c to
+    //confirm each one, and i or I to ignore case or not
+    fn substitute(&mut self, first: usize, last: usize, args: &str) {
+        let mut chars = args.chars();
+        let delimiter = chars.next().unwrap_or('/');
+        let (mut pattern, rest) = split_at_delimiter(chars.as_str(), delimiter);
+        let (replacement, flags) = match rest {
+            Some(rest) => {
+                let (replacement, flags) = split_at_delimiter(rest, delimiter);
+                (replacement, flags.unwrap_or(""))
+            }
+            None => (String::new(), ""),
+        };
+        let (mut global, mut confirm, mut case) = (false, false, "");
+        for flag in flags.trim_end().chars() {
+            match flag {
+                'g' => global = true,
+                'c' => confirm = true,
+                'i' => case = "\\c",
+                'I' => case = "\\C",
+                _ => {
+                    self.status_message = format!("Trailing characters: {}", flags);
+                    return;
+                }
+            }
+        }
+        //An empty pattern is the last one searched for
+        if pattern.is_empty() {
+            match &self.last_search {
+                Some(search) => pattern = search.pattern.clone(),
+                None => {
+                    self.status_message = String::from("No previous regular expression");
+                    return;
+                }
+            }
+        }
+        let regex = match self.search_regex(&format!("{}{}", pattern, case)) {
+            Ok(regex) => regex,
+            Err(message) => {
+                self.status_message = message;
+                return;
+            }
+        };
+        let replacement = expand_tilde(&replacement, &self.last_replacement);
+        self.last_replacement = replacement.clone();
+        self.add_to_search_history(&pattern);
+        let forward = self
+            .last_search
+            .as_ref()
+            .is_none_or(|search| search.forward);
+        self.last_search = Some(Search {
+            pattern: pattern.clone(),
+            forward,
+        });
+        self.highlight_search = true;
+        let mut substitution = Substitution {
+            regex,
+            replacement,
+            global,
+            y: first,
+            from: 0,
+            last,
+            after_match: false,
+            current: None,
+            count: 0,
+            n_lines: 0,
+            text: self.buffer.line(first).get_content(),
+            output: String::new(),
+            copied: 0,
+            unwritten: false,
+            line_changed: false,
+            cursor: self.cursor,
+        };
+        if self.next_substitute_match(&mut substitution).is_none() {
+            self.status_message = format!("Pattern not found: {}", pattern);
+            return;
+        }
+        if confirm {
+            self.mode = Mode::Confirm;
+            self.substitution = Some(substitution);
+            self.ask_confirmation();
+        } else {
+            while let Some((found, text)) = self.next_substitute_match(&mut substitution) {
+                self.replace_match(&mut substitution, found, &text);
+            }
+            self.finish_substitute(substitution);
+        }
+    }
+
+    //The next match and the text it's replaced with, with the substitution
+    //moved to its line
+    fn next_substitute_match(
+        &mut self,
+        substitution: &mut Substitution,
+    ) -> Option<(Range<usize>, String)> {
+        while substitution.y <= substitution.last {
+            let text = &substitution.text;
+            let found = match text.get(substitution.from..) {
+                Some(_) => substitution
+                    .regex
+                    .captures_at(text, substitution.from)
+                    .and_then(|captures| {
+                        let found = captures.get(0)?.range();
+                        Some((found, expand(&substitution.replacement, &captures)))
+                    }),
+                None => None,
+            };
+            match found {
+                //No empty match right after another one, like vim
+                Some((found, _))
+                    if substitution.after_match
+                        && found.is_empty()
+                        && found.start == substitution.from =>
+                {
+                    substitution.after_match = false;
+                    self.skip_char(substitution);
+                }
+                Some(found) => return Some(found),
+                None => self.next_substitute_line(substitution),
+            }
+        }
+        None
+    }
+
+    fn next_substitute_line(&mut self, substitution: &mut Substitution) {
+        self.write_substituted_line(substitution);
+        substitution.y += 1;
+        substitution.from = 0;
+        substitution.copied = 0;
+        substitution.output.clear();
+        substitution.after_match = false;
+        substitution.line_changed = false;
+        if substitution.y <= substitution.last {
+            substitution.text = self.buffer.line(substitution.y).get_content();
+        }
+    }
+
+    //Replaces line y with its text after the substitutions, if it had any.
+    //Line breaks added by them split it, leaving y at the last part
+    fn write_substituted_line(&mut self, substitution: &mut Substitution) {
+        if !substitution.unwritten {
+            return;
+        }
+        substitution.unwritten = false;
+        let text = substitution.output.clone() + &substitution.text[substitution.copied..];
+        let lines: Vec<Line> = text.split('\n').map(Line::new).collect();
+        let n_breaks = lines.len() - 1;
+        self.replace_lines(substitution.y, 1, lines);
+        substitution.y += n_breaks;
+        substitution.last += n_breaks;
+        if let Some(i) = substitution.output.rfind('\n') {
+            substitution.output = substitution.output.split_off(i + 1);
+        }
+        substitution.cursor = Cursor {
+            x: self.buffer.line(substitution.y).indent(),
+            y: substitution.y,
+        };
+    }
+
+    //Moves past the character at from, or to the next line at the end
+    fn skip_char(&mut self, substitution: &mut Substitution) {
+        match substitution.text[substitution.from..].chars().next() {
+            Some(c) => substitution.from += c.len_utf8(),
+            None => self.next_substitute_line(substitution),
+        }
+    }
+
+    //Goes on from the end of a match
+    fn continue_after(&mut self, substitution: &mut Substitution, end: usize, empty: bool) {
+        substitution.from = end;
+        substitution.after_match = !empty;
+        if !substitution.global {
+            self.next_substitute_line(substitution);
+        } else if empty {
+            self.skip_char(substitution);
+        }
+    }
+
+    //Replaces the match with text, which may break the line, and goes on
+    //after it. The buffer is changed once the whole line is done
+    fn replace_match(&mut self, substitution: &mut Substitution, found: Range<usize>, text: &str) {
+        let copied = substitution.copied;
+        substitution.output += &substitution.text[copied..found.start];
+        substitution.output += text;
+        substitution.copied = found.end;
+        substitution.count += 1;
+        if !substitution.line_changed {
+            substitution.n_lines += 1;
+        }
+        substitution.line_changed = true;
+        substitution.unwritten = true;
+        self.continue_after(substitution, found.end, found.is_empty());
+    }
+
+    //Shows the next match and asks what to do with it, or ends if there
+    //are no more
+    fn ask_confirmation(&mut self) {
+        let mut substitution = match self.substitution.take() {
+            Some(substitution) => substitution,
+            None => return,
+        };
+        match self.next_substitute_match(&mut substitution) {
+            Some((found, text)) => {
+                let line = self.buffer.line(substitution.y);
+                self.cursor = Cursor {
+                    x: line
+                        .display_range(substitution.written(found.clone()))
+                        .start,
+                    y: substitution.y,
+                };
+                self.status_message =
+                    format!("replace with {} (y/n/a/q/l)?", text.replace('\n', "^M"));
+                substitution.current = Some((substitution.y, found));
+                self.substitution = Some(substitution);
+            }
+            None => self.finish_substitute(substitution),
+        }
+    }
+
+    //y replaces the match, n skips it, a replaces it and all the rest, q
+    //ends and l replaces it and ends
+    pub(super) fn match_event_confirm(&mut self, event: Event) {
+        let key = match event {
+            Event::Key(KeyEvent {
+                code: KeyCode::Char(c),
+                ..
+            }) => c,
+            Event::Key(KeyEvent {
+                code: KeyCode::Esc, ..
+            }) => 'q',
+            _ => return,
+        };
+        let mut substitution = match self.substitution.take() {
+            Some(substitution) => substitution,
+            None => return,
+        };
+        let (y, found) = match substitution.current.take() {
+            Some(current) => current,
+            None => return,
+        };
+        if !"ynaql".contains(key) {
+            substitution.current = Some((y, found));
+            self.substitution = Some(substitution);
+            return;
+        }
+        if key == 'n' {
+            self.continue_after(&mut substitution, found.end, found.is_empty());
+        } else if key != 'q' {
+            //The match is found again to know what it's replaced with
+            substitution.from = found.start;
+            substitution.after_match = false;
+            if let Some((found, text)) = self.next_substitute_match(&mut substitution) {
+                self.replace_match(&mut substitution, found, &text);
+                self.write_substituted_line(&mut substitution);
+            }
+        }
+        match key {
+            'y' | 'n' => {
+                self.substitution = Some(substitution);
+                self.ask_confirmation();
+            }
+            'a' => {
+                while let Some((found, text)) = self.next_substitute_match(&mut substitution) {
+                    self.replace_match(&mut substitution, found, &text);
+                }
+                self.finish_substitute(substitution);
+            }
+            _ => self.finish_substitute(substitution),
+        }
+    }
+
+    //Leaves the cursor at the last line changed and tells how many
+    //substitutions were made, if more than one
+    fn finish_substitute(&mut self, mut substitution: Substitution) {
+        self.write_substituted_line(&mut substitution);
+        self.mode = Mode::Normal;
+        self.substitution = None;
+        self.cursor = substitution.cursor;
+        self.status_message = match (substitution.count, substitution.n_lines) {
+            (0, _) | (1, _) => String::new(),
+            (count, 1) => format!("{} substitutions on 1 line", count),
+            (count, n_lines) => format!("{} substitutions on {} lines", count, n_lines),
+        };
+    }
+
+    //Bytes of line y of the match waiting for confirmation, to highlight it
+    pub(super) fn confirming_match(&self, y: usize) -> Option<Range<usize>> {
+        match &self.substitution {
+            Some(
+                substitution @ Substitution {
+                    current: Some((match_y, found)),
+                    ..
+                },
+            ) if *match_y == y => {
+                let found = substitution.written(found.clone());
+                Some(self.buffer.line(y).display_range(found))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_helpers::{editor, type_keys};
+    use super::*;
+
+    fn lines(editor: &Editor) -> Vec<String> {
+        editor
+            .buffer
+            .lines()
+            .map(|line| line.get_content())
+            .collect()
+    }
+
+    #[test]
+    fn substitute_in_ranges() {
+        let mut editor = editor(&["a a", "a a", "a a", "b"]);
+        editor.run_command(":s/a/x/").unwrap();
+        assert_eq!(lines(&editor), vec!["x a", "a a", "a a", "b"]);
+        editor.run_command(":2,$s/a/y/g").unwrap();
+        assert_eq!(lines(&editor), vec!["x a", "y y", "y y", "b"]);
+        assert_eq!(editor.status_message, "4 substitutions on 2 lines");
+        assert_eq!(editor.cursor, Cursor { x: 0, y: 2 });
+        editor.run_command(":%s/A/z/gi").unwrap();
+        assert_eq!(lines(&editor), vec!["x z", "y y", "y y", "b"]);
+        editor.commit_change();
+        editor.run_command(":.+1,$-1s/y/w").unwrap();
+        assert_eq!(lines(&editor), vec!["x z", "w y", "w y", "b"]);
+        editor.undo(1);
+        assert_eq!(lines(&editor), vec!["x z", "y y", "y y", "b"]);
+        editor.run_command(":'<,'>s/y/w").unwrap();
+        assert_eq!(editor.status_message, "Mark not set");
+        editor.run_command(":5s/y/w").unwrap();
+        assert_eq!(editor.status_message, "Invalid range");
+        let huge = isize::MAX;
+        editor
+            .run_command(&format!(":$+{}+{}s/y/w", huge, huge))
+            .unwrap();
+        assert_eq!(editor.status_message, "Invalid range");
+        editor.run_command(":s/q/w").unwrap();
+        assert_eq!(editor.status_message, "Pattern not found: q");
+        editor.run_command(":s/y/w/x").unwrap();
+        assert_eq!(editor.status_message, "Trailing characters: x");
+    }
+
+    #[test]
+    fn replacement_expansion() {
+        let mut editor = editor(&["foo bar", "a/b", "baaac"]);
+        editor
+            .run_command(r":s/(\w+) (\w+)/\2 \1 & \& \0/")
+            .unwrap();
+        assert_eq!(lines(&editor)[0], "bar foo foo bar & foo bar");
+        editor
+            .run_command(r":s/(foo) (bar)/\u\1\U\2 \lX\E \Lab\uc/")
+            .unwrap();
+        assert_eq!(lines(&editor)[0], "bar foo FooBAR x abC & foo bar");
+        editor.run_command(r":2s/\//\r-/").unwrap();
+        assert_eq!(lines(&editor)[1..], ["a", "-b", "baaac"]);
+        editor.run_command(":$s/a*/-/g").unwrap();
+        assert_eq!(lines(&editor)[3], "-b-c-");
+        editor.run_command(r":$s/-/\r/g").unwrap();
+        assert_eq!(lines(&editor)[3..], ["", "b", "c", ""]);
+        assert_eq!(editor.cursor, Cursor { x: 0, y: 6 });
+    }
+
+    #[test]
+    fn match_the_line_as_it_was() {
+        let mut editor = editor(&["aaa", "a a"]);
+        editor.run_command(r":s/\<a/-/g").unwrap();
+        assert_eq!(lines(&editor), vec!["-aa", "a a"]);
+        editor.run_command(r":2s/\<a/-/gc").unwrap();
+        type_keys(&mut editor, "y");
+        assert_eq!(editor.confirming_match(1), Some(2..3));
+        type_keys(&mut editor, "y");
+        assert_eq!(lines(&editor), vec!["-aa", "- -"]);
+    }
+
+    #[test]
+    fn previous_replacement() {
+        let mut editor = editor(&["a b"]);
+        editor.run_command(":s/a/x/").unwrap();
+        editor.run_command(":s/b/~~/").unwrap();
+        assert_eq!(lines(&editor), vec!["x xx"]);
+        editor.run_command(r":s#x#\~#").unwrap();
+        assert_eq!(lines(&editor), vec!["~ xx"]);
+        editor.run_command(":s//y/").unwrap();
+        assert_eq!(lines(&editor), vec!["~ yx"]);
+    }
+
+    #[test]
+    fn confirm_each_match() {
+        let mut editor = editor(&["a a a", "a"]);
+        editor.run_command(":%s/a/b/gc").unwrap();
+        assert!(editor.mode == Mode::Confirm);
+        assert_eq!(editor.status_message, "replace with b (y/n/a/q/l)?");
+        assert_eq!(editor.confirming_match(0), Some(0..1));
+        type_keys(&mut editor, "yn");
+        assert_eq!(editor.cursor, Cursor { x: 4, y: 0 });
+        assert_eq!(editor.confirming_match(0), Some(4..5));
+        type_keys(&mut editor, "xy");
+        assert_eq!(editor.confirming_match(1), Some(0..1));
+        type_keys(&mut editor, "<Esc>");
+        assert!(editor.mode == Mode::Normal);
+        assert_eq!(lines(&editor), vec!["b a b", "a"]);
+        editor.undo(1);
+        assert_eq!(lines(&editor), vec!["a a a", "a"]);
+        editor.run_command(":%s/a/b/gc").unwrap();
+        type_keys(&mut editor, "na");
+        assert_eq!(lines(&editor), vec!["a b b", "b"]);
+        assert_eq!(editor.status_message, "3 substitutions on 2 lines");
+        editor.run_command(":%s/b/c/c").unwrap();
+        type_keys(&mut editor, "l");
+        assert_eq!(lines(&editor), vec!["a c b", "b"]);
+        assert!(editor.mode == Mode::Normal);
+    }
+}
@@ -0,0 +1,248 @@
+use super::file::write_atomically;
+use super::{Editor, Line, Mode};
+use crossterm::{
+    event::{Event, KeyCode, KeyEvent},
+    execute,
+    terminal::{disable_raw_mode, LeaveAlternateScreen},
+    Result,
+};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, exit};
+use std::time::Duration;
+
+const HEADER: &str = "tinate swap file 1";
+//The swap file is written after this many edits...
+pub const UPDATECOUNT: usize = 200;
+//...or after this long without key presses if there are unsaved edits
+pub const UPDATETIME: Duration = Duration::from_secs(4);
+
+//The swap file of "dir/name" is "dir/.name.swp", like in vim
+fn swap_file_path(path: &str) -> PathBuf {
+    let path = Path::new(path);
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.swp", name))
+}
+
+//Returns the lines stored in a swap file, or None if it isn't one
+fn parse_swap_file(contents: &str) -> Option<Vec<Line>> {
+    let mut lines = contents.split_terminator('\n');
+    if lines.next()? != HEADER {
+        return None;
+    }
+    lines.next()?; //Path of the edited file
+    lines.next()?; //Pid of the process that wrote it
+    Some(lines.map(Line::new).collect())
+}
+
+//Returns the pid of the process that wrote a swap file
+fn parse_swap_file_pid(contents: &str) -> Option<u32> {
+    let mut lines = contents.split_terminator('\n');
+    if lines.next()? != HEADER {
+        return None;
+    }
+    lines.nth(1)?.parse().ok()
+}
+
+//Whether another process with pid is running. Processes are looked up in the
+//proc filesystem, so where there isn't one they are never running
+fn is_running(pid: u32) -> bool {
+    pid != process::id() && Path::new("/proc").join(pid.to_string()).exists()
+}
+
+impl Editor {
+    pub(super) fn swap_file_exists(&self) -> bool {
+        swap_file_path(&self.file_name).exists()
+    }
+
+    //Stores the whole buffer in the swap file, so unsaved edits can be
+    //recovered if tinate dies. Read-only buffers don't have one so they
+    //don't overwrite the swap file of whoever is editing the file
+    pub(super) fn write_swap_file(&mut self) {
+        self.edits_since_swap = 0;
+        //A buffer without a file can't be recovered by opening it
+        if self.buffer.options().readonly || self.file_name.is_empty() {
+            return;
+        }
+        let path = swap_file_path(&self.file_name);
+        let header = format!("{}\n{}\n{}\n", HEADER, self.file_name, process::id());
+        let buffer = &self.buffer;
+        let result = write_atomically(&path.to_string_lossy(), |file| {
+            file.write_all(header.as_bytes())?;
+            for chunk in buffer.chunks() {
+                file.write_all(chunk.as_bytes())?;
+            }
+            Ok(())
+        });
+        if let Err(err) = result {
+            self.status_message = format!("Can't write swap file: {}", err);
+        }
+    }
+
+    pub(super) fn update_swap_file(&mut self) {
+        if self.edits_since_swap > 0 {
+            self.write_swap_file();
+        }
+    }
+
+    pub(super) fn remove_swap_file(&self) {
+        if !self.buffer.options().readonly {
+            let _ = fs::remove_file(swap_file_path(&self.file_name));
+        }
+    }
+
+    //Pid of the process that wrote the swap file, if it's still running.
+    //Its swap file isn't offered to be recovered or deleted, as it's in use
+    fn swap_file_owner(&self) -> Option<u32> {
+        let contents = fs::read_to_string(swap_file_path(&self.file_name)).ok()?;
+        parse_swap_file_pid(&contents).filter(|&pid| is_running(pid))
+    }
+
+    pub(super) fn ask_about_swap_file(&mut self) {
+        self.mode = Mode::Recovery;
+        let path = swap_file_path(&self.file_name);
+        self.status_message = match self.swap_file_owner() {
+            Some(pid) => format!(
+                "Found swap file {} of running process {}: [O]pen read-only, [E]dit anyway, [Q]uit",
+                path.display(),
+                pid
+            ),
+            None => format!(
+                "Found swap file {}: [R]ecover, [D]elete it, [O]pen read-only, [E]dit anyway, [Q]uit",
+                path.display()
+            ),
+        };
+    }
+
+    pub(super) fn match_event_recovery(&mut self, event: Event) -> Result<()> {
+        let key = match event {
+            Event::Key(KeyEvent {
+                code: KeyCode::Char(c),
+                ..
+            }) => c.to_ascii_lowercase(),
+            _ => return Ok(()),
+        };
+        match key {
+            'r' | 'd' if self.swap_file_owner().is_some() => {
+                self.ask_about_swap_file();
+                return Ok(());
+            }
+            'r' => {
+                if let Err(err) = self.recover() {
+                    self.status_message = format!("Can't recover: {}", err);
+                }
+            }
+            'd' => self.remove_swap_file(),
+            'o' => self.buffer.options_mut().readonly = true,
+            'e' => {}
+            'q' => {
+                execute!(io::stdout(), LeaveAlternateScreen)?;
+                //exit won't call destructors
+                disable_raw_mode()?;
+                exit(0);
+            }
+            _ => {
+                self.ask_about_swap_file();
+                return Ok(());
+            }
+        }
+        self.mode = Mode::Normal;
+        Ok(())
+    }
+
+    //Replaces the buffer with the contents of the swap file, as a change
+    //that can be undone to get the contents of the file back
+    fn recover(&mut self) -> io::Result<()> {
+        let contents = fs::read_to_string(swap_file_path(&self.file_name))?;
+        let mut lines = parse_swap_file(&contents)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a swap file"))?;
+        if lines.is_empty() {
+            lines.push(Line::new(""));
+        }
+        self.replace_lines(0, self.buffer.len(), lines);
+        self.commit_change();
+        self.status_message = String::from("Recovery completed. Check the changes and save them");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyModifiers;
+
+    fn key(c: char) -> Event {
+        Event::Key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE))
+    }
+
+    #[test]
+    fn swap_file_name() {
+        assert_eq!(swap_file_path("dir/a.txt"), PathBuf::from("dir/.a.txt.swp"));
+    }
+
+    #[test]
+    fn recover() -> Result<()> {
+        let name = "TestFileWithANameUnnecessarilyLongToAvoidCollisions9";
+        fs::write(name, "old\n")?;
+        let mut editor = Editor::new();
+        editor.load_to_buf(name)?;
+        editor.set_line(0, Line::new("new\r"));
+        editor.insert_line(1, Line::new(""));
+        editor.write_swap_file();
+        let mut editor = Editor::new();
+        editor.load_to_buf(name)?;
+        assert!(editor.mode == Mode::Recovery);
+        editor.match_event_recovery(key('r'))?;
+        assert!(editor.mode == Mode::Normal);
+        assert_eq!(editor.buffer, vec![Line::new("new\r"), Line::new("")]);
+        assert!(editor.history.is_modified());
+        editor.undo(1);
+        assert_eq!(editor.buffer, vec![Line::new("old")]);
+        editor.remove_swap_file();
+        assert!(!editor.swap_file_exists());
+        fs::remove_file(name)?;
+        Ok(())
+    }
+
+    #[test]
+    fn swap_file_in_use() -> Result<()> {
+        let name = "TestFileWithANameUnnecessarilyLongToAvoidCollisions13";
+        //The process of pid 1 is always running
+        let swap_file = format!("{}\n{}\n1\nnew\n", HEADER, name);
+        fs::write(swap_file_path(name), swap_file)?;
+        let mut editor = Editor::new();
+        editor.load_to_buf(name)?;
+        assert!(editor.status_message.contains("of running process 1"));
+        editor.match_event_recovery(key('r'))?;
+        editor.match_event_recovery(key('d'))?;
+        assert!(editor.mode == Mode::Recovery);
+        assert!(editor.swap_file_exists());
+        editor.match_event_recovery(key('e'))?;
+        assert_eq!(editor.buffer, vec![Line::new("")]);
+        fs::remove_file(swap_file_path(name))?;
+        Ok(())
+    }
+
+    #[test]
+    fn open_read_only() -> Result<()> {
+        let name = "TestFileWithANameUnnecessarilyLongToAvoidCollisions10";
+        let mut editor = Editor::new();
+        editor.file_name = name.to_owned();
+        editor.buffer.push(Line::new("a"));
+        editor.write_swap_file();
+        let mut editor = Editor::new();
+        editor.load_to_buf(name)?;
+        editor.match_event_recovery(key('O'))?;
+        assert!(editor.buffer.options().readonly);
+        editor.run_command(":w")?;
+        assert!(!Path::new(name).exists());
+        editor.remove_swap_file();
+        assert!(editor.swap_file_exists());
+        fs::remove_file(swap_file_path(name))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,615 @@
+use super::motion::{MotionRange, Parse, RangeKind};
+use super::{Cursor, Editor};
+use std::cmp::min;
+use std::ops::Range;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ObjectKind {
+    Word { big: bool },
+    Sentence,
+    Paragraph,
+    Quote(char),
+    //The opening and closing brackets
+    Block(char, char),
+    Tag,
+}
+
+//A region of structured text an operator can act on, like iw or a(. The
+//around ones also take the white space or the delimiters around it
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct TextObject {
+    pub kind: ObjectKind,
+    pub around: bool,
+}
+
+impl TextObject {
+    pub fn parse(keys: &str) -> Parse<TextObject> {
+        let mut chars = keys.chars();
+        let around = match chars.next() {
+            Some('i') => false,
+            Some('a') => true,
+            _ => return Parse::Invalid,
+        };
+        let kind = match chars.next() {
+            None => return Parse::Pending,
+            Some('w') => ObjectKind::Word { big: false },
+            Some('W') => ObjectKind::Word { big: true },
+            Some('s') => ObjectKind::Sentence,
+            Some('p') => ObjectKind::Paragraph,
+            Some(quote @ '"') | Some(quote @ '\'') | Some(quote @ '`') => ObjectKind::Quote(quote),
+            Some('(') | Some(')') | Some('b') => ObjectKind::Block('(', ')'),
+            Some('[') | Some(']') => ObjectKind::Block('[', ']'),
+            Some('{') | Some('}') | Some('B') => ObjectKind::Block('{', '}'),
+            Some('<') | Some('>') => ObjectKind::Block('<', '>'),
+            Some('t') => ObjectKind::Tag,
+            Some(_) => return Parse::Invalid,
+        };
+        match chars.next() {
+            Some(_) => Parse::Invalid,
+            None => Parse::Complete(TextObject { kind, around }),
+        }
+    }
+}
+
+//A grapheme of the buffer and its position. Line breaks are "\n" items at
+//the len of their line
+type Item = (Cursor, String);
+
+fn is_blank(s: &str) -> bool {
+    s.chars().all(char::is_whitespace)
+}
+
+//Picks count spans from the i-th, each one followed by the blank span after
+//it for around objects, or preceded by the one before if there is none.
+//Returns the first and the last span taken
+fn pick_spans(blanks: &[bool], i: usize, count: usize, around: bool) -> (usize, usize) {
+    let mut end = i;
+    for n in 0..count {
+        if n > 0 {
+            if end + 1 == blanks.len() {
+                break;
+            }
+            end += 1;
+        }
+        if around && end + 1 < blanks.len() && blanks[end] != blanks[end + 1] {
+            end += 1;
+        }
+    }
+    if around && !blanks[i] && !blanks[end] && i > 0 && blanks[i - 1] {
+        (i - 1, end)
+    } else {
+        (i, end)
+    }
+}
+
+impl Editor {
+    //The text the object covers around the cursor, None if there is none
+    pub(super) fn text_object_range(
+        &self,
+        object: TextObject,
+        count: usize,
+    ) -> Option<MotionRange> {
+        let cursor = Cursor {
+            x: self.x(true),
+            y: self.y(),
+        };
+        let around = object.around;
+        match object.kind {
+            ObjectKind::Word { big } => self.word_object(cursor, big, around, count),
+            ObjectKind::Sentence => self.sentence_object(cursor, around, count),
+            ObjectKind::Paragraph => Some(self.paragraph_object(cursor.y, around, count)),
+            ObjectKind::Quote(quote) => self.quote_object(cursor, quote, around),
+            ObjectKind::Block(open, close) => self.block_object(cursor, open, close, around, count),
+            ObjectKind::Tag => self.tag_object(cursor, around, count),
+        }
+    }
+
+    //Graphemes of lines, each of them followed by a line break except the
+    //last line of the buffer
+    fn items(&self, lines: Range<usize>) -> Vec<Item> {
+        let mut items = Vec::new();
+        for y in lines {
+            let line = self.buffer.line(y);
+            items.extend(
+                line.graphemes()
+                    .map(|(x, grapheme)| (Cursor { x, y }, grapheme.to_owned())),
+            );
+            if y + 1 < self.buffer.len() {
+                items.push((Cursor { x: line.len(), y }, String::from("\n")));
+            }
+        }
+        items
+    }
+
+    //The charwise range from items[start] up to items[end], without it
+    fn items_range(&self, items: &[Item], start: usize, end: usize) -> MotionRange {
+        let end = match items.get(end) {
+            Some(&(pos, _)) => pos,
+            None => {
+                let (pos, grapheme) = &items[end - 1];
+                let line = self.buffer.line(pos.y);
+                match grapheme.as_ref() {
+                    "\n" => Cursor { x: 0, y: pos.y + 1 },
+                    _ => Cursor {
+                        x: line.next_valid_index(pos.x).unwrap_or_else(|| line.len()),
+                        y: pos.y,
+                    },
+                }
+            }
+        };
+        MotionRange {
+            start: items[start].0,
+            end,
+            kind: RangeKind::Charwise,
+        }
+    }
+
+    fn word_object(
+        &self,
+        cursor: Cursor,
+        big: bool,
+        around: bool,
+        count: usize,
+    ) -> Option<MotionRange> {
+        let line = self.buffer.line(cursor.y);
+        //The words of the line and the blanks between them
+        let mut spans: Vec<(Range<usize>, bool)> = Vec::new();
+        let mut x = 0;
+        for word in line.words(big) {
+            if word.start > x {
+                spans.push((x..word.start, true));
+            }
+            x = word.end;
+            spans.push((word, false));
+        }
+        if x < line.len() {
+            spans.push((x..line.len(), true));
+        }
+        let i = spans
+            .iter()
+            .position(|(span, _)| span.contains(&cursor.x))?;
+        let blanks: Vec<bool> = spans.iter().map(|&(_, blank)| blank).collect();
+        let (first, last) = pick_spans(&blanks, i, count, around);
+        Some(MotionRange {
+            start: Cursor {
+                x: spans[first].0.start,
+                y: cursor.y,
+            },
+            end: Cursor {
+                x: spans[last].0.end,
+                y: cursor.y,
+            },
+            kind: RangeKind::Charwise,
+        })
+    }
+
+    //The lines around y that are all empty or all not empty
+    fn paragraph_bounds(&self, y: usize) -> (usize, usize) {
+        let is_empty = |y| self.buffer.line(y).is_empty();
+        let (mut first, mut last) = (y, y);
+        while first > 0 && is_empty(first - 1) == is_empty(y) {
+            first -= 1;
+        }
+        while last + 1 < self.buffer.len() && is_empty(last + 1) == is_empty(y) {
+            last += 1;
+        }
+        (first, last)
+    }
+
+    //Sentences end at a '.', '!' or '?', maybe followed by closing quotes or
+    //brackets, and then white space. They don't go beyond their paragraph
+    fn sentence_object(&self, cursor: Cursor, around: bool, count: usize) -> Option<MotionRange> {
+        let (first, last) = self.paragraph_bounds(cursor.y);
+        let mut items = self.items(first..last + 1);
+        if items.last().is_some_and(|(_, grapheme)| grapheme == "\n") {
+            items.pop();
+        }
+        let i = items.iter().position(|&(pos, _)| pos == cursor)?;
+        let mut spans: Vec<(usize, usize, bool)> = Vec::new();
+        let mut j = 0;
+        while j < items.len() {
+            let start = j;
+            let blank = is_blank(&items[j].1);
+            if blank {
+                while j < items.len() && is_blank(&items[j].1) {
+                    j += 1;
+                }
+            } else {
+                while j < items.len() {
+                    let grapheme = items[j].1.as_str();
+                    j += 1;
+                    if [".", "!", "?"].contains(&grapheme) {
+                        while j < items.len()
+                            && [")", "]", "\"", "'"].contains(&items[j].1.as_str())
+                        {
+                            j += 1;
+                        }
+                        if j == items.len() || is_blank(&items[j].1) {
+                            break;
+                        }
+                    }
+                }
+            }
+            spans.push((start, j, blank));
+        }
+        let k = spans
+            .iter()
+            .position(|&(start, end, _)| start <= i && i < end)?;
+        let blanks: Vec<bool> = spans.iter().map(|&(_, _, blank)| blank).collect();
+        let (first, last) = pick_spans(&blanks, k, count, around);
+        Some(self.items_range(&items, spans[first].0, spans[last].1))
+    }
+
+    fn paragraph_object(&self, y: usize, around: bool, count: usize) -> MotionRange {
+        let is_empty = |y| self.buffer.line(y).is_empty();
+        let (mut first, mut last) = self.paragraph_bounds(y);
+        let n = if around {
+            count.saturating_mul(2)
+        } else {
+            count
+        };
+        for _ in 1..n {
+            if last + 1 == self.buffer.len() {
+                break;
+            }
+            last = self.paragraph_bounds(last + 1).1;
+        }
+        //Without empty lines after the paragraph, ap takes the ones before it
+        if around && !is_empty(y) && !is_empty(last) && first > 0 {
+            first = self.paragraph_bounds(first - 1).0;
+        }
+        MotionRange {
+            start: Cursor { x: 0, y: first },
+            end: Cursor { x: 0, y: last },
+            kind: RangeKind::Linewise,
+        }
+    }
+
+    //Quotes are paired from the start of the line, skipping escaped ones.
+    //If the cursor isn't inside a pair, the next one in the line is taken
+    fn quote_object(&self, cursor: Cursor, quote: char, around: bool) -> Option<MotionRange> {
+        let line = self.buffer.line(cursor.y);
+        let graphemes: Vec<(usize, &str)> = line.graphemes().collect();
+        let quote = quote.to_string();
+        let quotes: Vec<usize> = graphemes
+            .iter()
+            .enumerate()
+            .filter(|&(k, &(_, grapheme))| {
+                grapheme == quote && (k == 0 || graphemes[k - 1].1 != "\\")
+            })
+            .map(|(_, &(x, _))| x)
+            .collect();
+        let (open, close) = quotes
+            .chunks_exact(2)
+            .map(|pair| (pair[0], pair[1]))
+            .find(|&(_, close)| cursor.x <= close)?;
+        let after = |x| line.next_valid_index(x).unwrap_or_else(|| line.len());
+        let (mut start, mut end) = if around {
+            (open, after(close))
+        } else {
+            (after(open), close)
+        };
+        if around {
+            let is_blank_at = |x| line.grapheme_at(x).is_some_and(is_blank);
+            if is_blank_at(end) {
+                while is_blank_at(end) {
+                    end = after(end);
+                }
+            } else {
+                while line.prev_valid_index(start).is_some_and(is_blank_at) {
+                    start = line.prev_valid_index(start).unwrap_or(0);
+                }
+            }
+        }
+        Some(MotionRange {
+            start: Cursor {
+                x: start,
+                y: cursor.y,
+            },
+            end: Cursor {
+                x: end,
+                y: cursor.y,
+            },
+            kind: RangeKind::Charwise,
+        })
+    }
+
+    //The count-th pair of brackets around the cursor. When the brackets are
+    //on lines of their own, the inner object is made of whole lines
+    fn block_object(
+        &self,
+        cursor: Cursor,
+        open: char,
+        close: char,
+        around: bool,
+        count: usize,
+    ) -> Option<MotionRange> {
+        let (open, close) = (open.to_string(), close.to_string());
+        //The opening bracket is searched for going back from the cursor a
+        //line at a time, and the closing one going forward from it
+        let mut y = cursor.y;
+        let mut line = self.items(y..y + 1);
+        let i = line.iter().position(|&(pos, _)| pos == cursor)?;
+        //A closing bracket under the cursor is the end of the block
+        let mut skip = if line[i].1 == close { 1 } else { 0 };
+        line.truncate(i + 1);
+        let (mut level, mut depth) = (count, 0);
+        let start = 'search: loop {
+            for (pos, grapheme) in line.iter().rev().skip(skip) {
+                if *grapheme == close {
+                    depth += 1;
+                } else if *grapheme == open {
+                    if depth == 0 {
+                        level -= 1;
+                        if level == 0 {
+                            break 'search *pos;
+                        }
+                    } else {
+                        depth -= 1;
+                    }
+                }
+            }
+            y = y.checked_sub(1)?;
+            line = self.items(y..y + 1);
+            skip = 0;
+        };
+        let mut y = start.y;
+        let mut line = self.items(y..y + 1);
+        let mut skip = line.iter().position(|&(pos, _)| pos == start)? + 1;
+        let mut depth = 0;
+        let end = 'search: loop {
+            for (pos, grapheme) in line.iter().skip(skip) {
+                if *grapheme == open {
+                    depth += 1;
+                } else if *grapheme == close {
+                    if depth == 0 {
+                        break 'search *pos;
+                    }
+                    depth -= 1;
+                }
+            }
+            y += 1;
+            if y == self.buffer.len() {
+                return None;
+            }
+            line = self.items(y..y + 1);
+            skip = 0;
+        };
+        let items = self.items(start.y..end.y + 1);
+        let start = items.iter().position(|&(pos, _)| pos == start)?;
+        let end = items.iter().position(|&(pos, _)| pos == end)?;
+        if around {
+            return Some(self.items_range(&items, start, end + 1));
+        }
+        let (open_y, close_y) = (items[start].0.y, items[end].0.y);
+        let close_line = self.buffer.line(close_y);
+        if items[start + 1].1 == "\n"
+            && close_line.indent() == items[end].0.x
+            && close_y > open_y + 1
+        {
+            return Some(MotionRange {
+                start: Cursor {
+                    x: 0,
+                    y: open_y + 1,
+                },
+                end: Cursor {
+                    x: 0,
+                    y: close_y - 1,
+                },
+                kind: RangeKind::Linewise,
+            });
+        }
+        Some(self.items_range(&items, start + 1, end))
+    }
+
+    //The count-th pair of XML/HTML tags around the cursor, like <a href="">
+    //and </a>. Self-closing tags and comments are skipped. The lines around
+    //the cursor are searched, twice as many each time the pair isn't found.
+    //Tags outside of them can only make up pairs enclosing the ones inside
+    fn tag_object(&self, cursor: Cursor, around: bool, count: usize) -> Option<MotionRange> {
+        let mut reach = 1;
+        loop {
+            let first = cursor.y.saturating_sub(reach);
+            let last = min(cursor.y.saturating_add(reach), self.buffer.len() - 1);
+            let items = self.items(first..last + 1);
+            let range = self.tag_range(&items, cursor, around, count);
+            if range.is_some() || (first == 0 && last == self.buffer.len() - 1) {
+                return range;
+            }
+            reach = reach.saturating_mul(2);
+        }
+    }
+
+    fn tag_range(
+        &self,
+        items: &[Item],
+        cursor: Cursor,
+        around: bool,
+        count: usize,
+    ) -> Option<MotionRange> {
+        let i = items.iter().position(|&(pos, _)| pos == cursor)?;
+        //Pairs of item ranges of an opening tag and its closing one
+        let mut pairs: Vec<(Range<usize>, Range<usize>)> = Vec::new();
+        let mut open_tags: Vec<(Range<usize>, String)> = Vec::new();
+        let mut k = 0;
+        while k < items.len() {
+            if items[k].1 != "<" {
+                k += 1;
+                continue;
+            }
+            let end = match (k + 1..items.len()).find(|&j| items[j].1 == ">" || items[j].1 == "<") {
+                Some(end) if items[end].1 == ">" => end,
+                Some(end) => {
+                    k = end;
+                    continue;
+                }
+                None => break,
+            };
+            let text: String = items[k + 1..end]
+                .iter()
+                .map(|(_, grapheme)| grapheme.as_str())
+                .collect();
+            let tag = k..end + 1;
+            k = end + 1;
+            if text.ends_with('/') || text.starts_with('!') || text.starts_with('?') {
+                continue;
+            }
+            if let Some(name) = text.strip_prefix('/') {
+                let name = name.trim();
+                if let Some(j) = open_tags.iter().rposition(|(_, open)| open == name) {
+                    pairs.push((open_tags[j].0.clone(), tag));
+                    open_tags.truncate(j);
+                }
+            } else {
+                let name = text.split_whitespace().next().unwrap_or("");
+                open_tags.push((tag, name.to_owned()));
+            }
+        }
+        //Closing tags are found from the innermost pair outwards
+        let (open, close) = pairs
+            .into_iter()
+            .filter(|(open, close)| open.start <= i && i < close.end)
+            .nth(count - 1)?;
+        if around {
+            Some(self.items_range(items, open.start, close.end))
+        } else {
+            Some(self.items_range(items, open.end, close.start))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_helpers::editor;
+    use super::*;
+
+    fn editor_at(lines: &[&str], x: usize, y: usize) -> Editor {
+        let mut editor = editor(lines);
+        editor.cursor = Cursor { x, y };
+        editor
+    }
+
+    fn object(keys: &str) -> TextObject {
+        match TextObject::parse(keys) {
+            Parse::Complete(object) => object,
+            _ => panic!("Not a text object: {}", keys),
+        }
+    }
+
+    //The start and end xs of a range in a single line
+    fn xs(editor: &Editor, keys: &str, count: usize) -> Option<(usize, usize)> {
+        editor
+            .text_object_range(object(keys), count)
+            .map(|range| (range.start.x, range.end.x))
+    }
+
+    #[test]
+    fn parse_objects() {
+        assert_eq!(TextObject::parse("i"), Parse::Pending);
+        assert_eq!(TextObject::parse("iz"), Parse::Invalid);
+        assert_eq!(object("ab").kind, ObjectKind::Block('(', ')'));
+        assert!(object("ab").around);
+    }
+
+    #[test]
+    fn words() {
+        let editor = editor_at(&["foo  bár-baz qux"], 6, 0);
+        assert_eq!(xs(&editor, "iw", 1), Some((5, 8)));
+        assert_eq!(xs(&editor, "aw", 1), Some((3, 8)));
+        assert_eq!(xs(&editor, "iW", 1), Some((5, 12)));
+        assert_eq!(xs(&editor, "aW", 1), Some((5, 13)));
+        assert_eq!(xs(&editor, "iw", 3), Some((5, 12)));
+        let editor = editor_at(&["foo  bar"], 3, 0);
+        assert_eq!(xs(&editor, "iw", 1), Some((3, 5)));
+        assert_eq!(xs(&editor, "aw", 1), Some((3, 8)));
+    }
+
+    #[test]
+    fn sentences() {
+        let editor = editor_at(&["One. Two (2)!  Three", "goes on. Four"], 6, 0);
+        assert_eq!(xs(&editor, "is", 1), Some((5, 13)));
+        assert_eq!(xs(&editor, "as", 1), Some((5, 15)));
+        let range = editor.text_object_range(object("is"), 3).unwrap();
+        assert_eq!(range.end, Cursor { x: 8, y: 1 });
+    }
+
+    #[test]
+    fn paragraphs() {
+        let editor = editor_at(&["a", "b", "", "", "c"], 0, 1);
+        let lines = |keys, count| {
+            editor
+                .text_object_range(object(keys), count)
+                .map(|range| (range.start.y, range.end.y, range.kind))
+        };
+        assert_eq!(lines("ip", 1), Some((0, 1, RangeKind::Linewise)));
+        assert_eq!(lines("ap", 1), Some((0, 3, RangeKind::Linewise)));
+        assert_eq!(lines("ip", 3), Some((0, 4, RangeKind::Linewise)));
+        let editor = editor_at(&["a", "", "c"], 0, 2);
+        assert_eq!(
+            editor
+                .text_object_range(object("ap"), 1)
+                .map(|range| (range.start.y, range.end.y)),
+            Some((1, 2))
+        );
+    }
+
+    #[test]
+    fn quotes() {
+        let editor = editor_at(&[r#"x "a\"b" y 'c'"#], 0, 0);
+        assert_eq!(xs(&editor, "i\"", 1), Some((3, 7)));
+        assert_eq!(xs(&editor, "a\"", 1), Some((2, 9)));
+        assert_eq!(xs(&editor, "a'", 1), Some((10, 14)));
+        assert_eq!(xs(&editor, "i`", 1), None);
+    }
+
+    #[test]
+    fn blocks() {
+        let editor = editor_at(&["f(a, (b), c)"], 6, 0);
+        assert_eq!(xs(&editor, "i(", 1), Some((6, 7)));
+        assert_eq!(xs(&editor, "a)", 1), Some((5, 8)));
+        assert_eq!(xs(&editor, "ib", 2), Some((2, 11)));
+        assert_eq!(xs(&editor, "i[", 1), None);
+        let editor = editor_at(&["f(a, (b), c)"], 11, 0);
+        assert_eq!(xs(&editor, "i(", 1), Some((2, 11)));
+    }
+
+    #[test]
+    fn multiline_block() {
+        let editor = editor_at(&["if x {", "    a;", "    b;", "}"], 4, 1);
+        let range = editor.text_object_range(object("iB"), 1).unwrap();
+        assert_eq!((range.start.y, range.end.y), (1, 2));
+        assert_eq!(range.kind, RangeKind::Linewise);
+        let range = editor.text_object_range(object("a{"), 1).unwrap();
+        assert_eq!(
+            (range.start, range.end),
+            (Cursor { x: 5, y: 0 }, Cursor { x: 1, y: 3 })
+        );
+    }
+
+    #[test]
+    fn tags() {
+        let editor = editor_at(&["<div a=\"1\"><b>x<br/></b> y</div>"], 15, 0);
+        assert_eq!(xs(&editor, "it", 1), Some((14, 20)));
+        assert_eq!(xs(&editor, "at", 1), Some((11, 24)));
+        assert_eq!(xs(&editor, "it", 2), Some((11, 26)));
+        assert_eq!(xs(&editor, "it", 3), None);
+    }
+
+    #[test]
+    fn multiline_tags() {
+        let mut lines = vec!["<ul>", "<li>", "a", "</li>"];
+        lines.extend(["x"; 5]);
+        lines.push("</ul>");
+        let editor = editor_at(&lines, 0, 2);
+        let range = editor.text_object_range(object("at"), 2).unwrap();
+        assert_eq!(
+            (range.start, range.end),
+            (Cursor { x: 0, y: 0 }, Cursor { x: 5, y: 9 })
+        );
+        let range = editor.text_object_range(object("it"), 1).unwrap();
+        assert_eq!(
+            (range.start, range.end),
+            (Cursor { x: 4, y: 1 }, Cursor { x: 0, y: 3 })
+        );
+    }
+}
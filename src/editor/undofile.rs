@@ -0,0 +1,361 @@
+use super::file::write_atomically;
+use super::history::{Change, Edit, History, Node};
+use super::{Cursor, Editor};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::Split;
+use std::time::{Duration, UNIX_EPOCH};
+
+const HEADER: &str = "tinate undo file 1";
+
+//The undo history of "dir/name" is stored in "dir/.name.un~", like vim does
+fn undo_file_path(path: &str) -> PathBuf {
+    let path = Path::new(path);
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.un~", name))
+}
+
+fn canonical_path(path: &str) -> String {
+    fs::canonicalize(path)
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| path.to_owned())
+}
+
+//FNV-1a, used instead of DefaultHasher because it must be stable across builds
+fn hash<'a>(chunks: impl Iterator<Item = &'a str>) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in chunks.flat_map(str::bytes) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+fn serialize(history: &History, path: &str, hash: u64) -> String {
+    let mut out = format!(
+        "{}\n{}\n{:016x}\n{} {}\n",
+        HEADER,
+        path,
+        hash,
+        history.nodes.len(),
+        history.current
+    );
+    for node in history.nodes.iter() {
+        let time = node.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let change = &node.change;
+        out.push_str(&format!(
+            "{} {} {} {} {} {} {} {} {}\n",
+            node.parent,
+            node.redo_child.map_or(String::from("-"), |i| i.to_string()),
+            node.depth,
+            time.as_secs(),
+            change.cursor_before.x,
+            change.cursor_before.y,
+            change.cursor_after.x,
+            change.cursor_after.y,
+            change.edits.len()
+        ));
+        for edit in change.edits.iter() {
+            out.push_str(&format!(
+                "{} {} {}\n",
+                edit.y,
+                edit.old.len(),
+                edit.new.len()
+            ));
+            for line in edit.old.iter().chain(edit.new.iter()) {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+    }
+    out
+}
+
+fn next_numbers(lines: &mut Split<char>, n: usize) -> Option<Vec<String>> {
+    let numbers: Vec<String> = lines
+        .next()?
+        .split(' ')
+        .map(|number| number.to_owned())
+        .collect();
+    if numbers.len() == n {
+        Some(numbers)
+    } else {
+        None
+    }
+}
+
+fn next_strings(lines: &mut Split<char>, n: usize) -> Option<Vec<String>> {
+    (0..n)
+        .map(|_| lines.next().map(|line| line.to_owned()))
+        .collect()
+}
+
+//Number of lines left by change, or None if its edits don't fit in n_lines
+fn lines_after(change: &Change, mut n_lines: usize) -> Option<usize> {
+    for edit in change.edits.iter() {
+        if edit.y.checked_add(edit.old.len())? > n_lines {
+            return None;
+        }
+        n_lines = n_lines - edit.old.len() + edit.new.len();
+    }
+    Some(n_lines)
+}
+
+//Number of lines there were before change left n_lines
+fn lines_before(change: &Change, mut n_lines: usize) -> Option<usize> {
+    for edit in change.edits.iter().rev() {
+        n_lines = n_lines.checked_sub(edit.new.len())? + edit.old.len();
+    }
+    Some(n_lines)
+}
+
+//Returns None if the file is corrupt or doesn't belong to path with contents
+//hash, which has n_lines
+fn deserialize(s: &str, path: &str, hash: u64, n_lines: usize) -> Option<History> {
+    //Not lines(), which would drop a '\r' ending a stored line
+    let mut lines = s.split('\n');
+    if lines.next()? != HEADER
+        || lines.next()? != path
+        || u64::from_str_radix(lines.next()?, 16).ok()? != hash
+    {
+        return None;
+    }
+    let numbers = next_numbers(&mut lines, 2)?;
+    let n_nodes: usize = numbers[0].parse().ok()?;
+    let current: usize = numbers[1].parse().ok()?;
+    let mut nodes = Vec::new();
+    for i in 0..n_nodes {
+        let numbers = next_numbers(&mut lines, 9)?;
+        let redo_child = match numbers[1].as_ref() {
+            "-" => None,
+            child => Some(child.parse().ok()?),
+        };
+        let numbers = numbers
+            .iter()
+            .enumerate()
+            .map(|(i, number)| if i == 1 { Some(0) } else { number.parse().ok() })
+            .collect::<Option<Vec<usize>>>()?;
+        let mut change = Change::new(Cursor {
+            x: numbers[4],
+            y: numbers[5],
+        });
+        change.cursor_after = Cursor {
+            x: numbers[6],
+            y: numbers[7],
+        };
+        for _ in 0..numbers[8] {
+            let sizes = next_numbers(&mut lines, 3)?
+                .iter()
+                .map(|number| number.parse().ok())
+                .collect::<Option<Vec<usize>>>()?;
+            change.edits.push(Edit {
+                y: sizes[0],
+                old: next_strings(&mut lines, sizes[1])?,
+                new: next_strings(&mut lines, sizes[2])?,
+            });
+        }
+        //Parents come before their children, so the tree has no cycles. The
+        //root is its own parent
+        if numbers[0] >= i.max(1) || redo_child.is_some_and(|child| child >= n_nodes) {
+            return None;
+        }
+        nodes.push(Node {
+            parent: numbers[0],
+            change,
+            time: UNIX_EPOCH.checked_add(Duration::from_secs(numbers[3] as u64))?,
+            redo_child,
+            depth: numbers[2],
+        });
+    }
+    if current >= n_nodes {
+        return None;
+    }
+    //The redo child of a node must be one of its children
+    for (i, node) in nodes.iter().enumerate() {
+        if node
+            .redo_child
+            .is_some_and(|child| child == 0 || nodes[child].parent != i)
+        {
+            return None;
+        }
+    }
+    //Every change must fit in the lines there are when it's replayed, going
+    //from the root, whose lines are found undoing from current
+    let mut root_lines = n_lines;
+    let mut i = current;
+    while i != 0 {
+        root_lines = lines_before(&nodes[i].change, root_lines)?;
+        i = nodes[i].parent;
+    }
+    let mut node_lines = vec![root_lines];
+    for node in nodes.iter().skip(1) {
+        node_lines.push(lines_after(&node.change, node_lines[node.parent])?);
+    }
+    if node_lines[current] != n_lines {
+        return None;
+    }
+    let mut history = History::new();
+    history.nodes = nodes;
+    history.current = current;
+    history.mark_saved();
+    Some(history)
+}
+
+impl Editor {
+    fn buffer_hash(&self) -> u64 {
+        hash(self.buffer.chunks())
+    }
+
+    //Restores the history saved by write_undo_file, unless the file changed since then
+    pub(super) fn read_undo_file(&mut self) {
+        let contents = match fs::read_to_string(undo_file_path(&self.file_name)) {
+            Ok(contents) => contents,
+            Err(_) => return,
+        };
+        let path = canonical_path(&self.file_name);
+        if let Some(history) = deserialize(&contents, &path, self.buffer_hash(), self.buffer.len())
+        {
+            self.history = history;
+        }
+    }
+
+    pub(super) fn write_undo_file(&mut self) -> io::Result<()> {
+        self.commit_change();
+        if self.history.nodes.len() <= 1 {
+            return Ok(());
+        }
+        let path = canonical_path(&self.file_name);
+        let contents = serialize(&self.history, &path, self.buffer_hash());
+        let path = undo_file_path(&self.file_name);
+        write_atomically(&path.to_string_lossy(), |file| {
+            file.write_all(contents.as_bytes())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Line;
+    use super::*;
+    use crossterm::Result;
+
+    #[test]
+    fn undo_file_name() {
+        assert_eq!(undo_file_path("dir/a.txt"), PathBuf::from("dir/.a.txt.un~"));
+        assert_eq!(undo_file_path("a.txt"), PathBuf::from(".a.txt.un~"));
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        let mut editor = Editor::new();
+        editor.buffer.push(Line::new("one"));
+        editor.set_line(0, Line::new("two\r"));
+        editor.insert_line(1, Line::new(""));
+        editor.commit_change();
+        editor.undo(1);
+        editor.remove_line(0);
+        editor.commit_change();
+        let s = serialize(&editor.history, "path", 42);
+        let history = deserialize(&s, "path", 42, editor.buffer.len()).unwrap();
+        assert_eq!(history.current, editor.history.current);
+        assert_eq!(history.nodes.len(), editor.history.nodes.len());
+        for (a, b) in history.nodes.iter().zip(editor.history.nodes.iter()) {
+            assert_eq!(a.change, b.change);
+            assert_eq!(a.redo_child, b.redo_child);
+        }
+        assert!(deserialize(&s, "path", 43, editor.buffer.len()).is_none());
+        assert!(deserialize(&s, "other", 42, editor.buffer.len()).is_none());
+    }
+
+    #[test]
+    fn reject_cycles() {
+        let mut editor = Editor::new();
+        editor.buffer.push(Line::new("one"));
+        editor.set_line(0, Line::new("two"));
+        editor.commit_change();
+        let s = serialize(&editor.history, "path", 42);
+        assert!(deserialize(&s, "path", 42, editor.buffer.len()).is_some());
+        //Node 1 made its own parent
+        let s = s.replacen("\n0 - 1 ", "\n1 - 1 ", 1);
+        assert!(s.contains("\n1 - 1 "));
+        assert!(deserialize(&s, "path", 42, editor.buffer.len()).is_none());
+    }
+
+    #[test]
+    fn reject_invalid_nodes() {
+        let mut editor = Editor::new();
+        editor.buffer.push(Line::new("one"));
+        editor.set_line(0, Line::new("two"));
+        editor.commit_change();
+        editor.undo(1);
+        editor.insert_line(1, Line::new("three"));
+        editor.commit_change();
+        let s = serialize(&editor.history, "path", 42);
+        assert!(deserialize(&s, "path", 42, 2).is_some());
+        //Node 2 made the redo child of node 1, though they are siblings
+        let child = s.replacen("\n0 - 1 ", "\n0 2 1 ", 1);
+        assert_ne!(child, s);
+        assert!(deserialize(&child, "path", 42, 2).is_none());
+        //The edits don't fit in the lines of the buffer
+        assert!(deserialize(&s, "path", 42, 1).is_none());
+        let edit = s.replacen("\n1 0 1\n", "\n5 0 1\n", 1);
+        assert_ne!(edit, s);
+        assert!(deserialize(&edit, "path", 42, 2).is_none());
+    }
+
+    #[test]
+    fn reject_huge_times() {
+        let mut editor = Editor::new();
+        editor.buffer.push(Line::new("one"));
+        editor.set_line(0, Line::new("two"));
+        editor.commit_change();
+        let s = serialize(&editor.history, "path", 42);
+        let mut lines: Vec<String> = s.lines().map(String::from).collect();
+        let mut numbers: Vec<&str> = lines[5].split(' ').collect();
+        let time = u64::MAX.to_string();
+        numbers[3] = &time;
+        lines[5] = numbers.join(" ");
+        assert!(deserialize(&lines.join("\n"), "path", 42, editor.buffer.len()).is_none());
+    }
+
+    #[test]
+    fn undo_after_reopening() -> Result<()> {
+        let name = "TestFileWithANameUnnecessarilyLongToAvoidCollisions3";
+        let mut editor = Editor::new();
+        editor.file_name = name.to_owned();
+        editor.buffer.push(Line::new("old"));
+        editor.set_line(0, Line::new("new"));
+        editor.commit_change();
+        assert!(editor.write_file(false, ""));
+        let mut editor = Editor::new();
+        editor.load_to_buf(name)?;
+        editor.undo(1);
+        assert_eq!(editor.buffer, vec![Line::new("old")]);
+        fs::remove_file(name)?;
+        fs::remove_file(undo_file_path(name))?;
+        Ok(())
+    }
+
+    #[test]
+    fn changed_file_discards_history() -> Result<()> {
+        let name = "TestFileWithANameUnnecessarilyLongToAvoidCollisions4";
+        let mut editor = Editor::new();
+        editor.file_name = name.to_owned();
+        editor.buffer.push(Line::new("old"));
+        editor.set_line(0, Line::new("new"));
+        editor.commit_change();
+        assert!(editor.write_file(false, ""));
+        fs::write(name, "changed\n")?;
+        let mut editor = Editor::new();
+        editor.load_to_buf(name)?;
+        editor.undo(1);
+        assert_eq!(editor.buffer, vec![Line::new("changed")]);
+        fs::remove_file(name)?;
+        fs::remove_file(undo_file_path(name))?;
+        Ok(())
+    }
+}
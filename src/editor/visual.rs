@@ -0,0 +1,465 @@
+use super::motion::{split_count, Motion, MotionRange, Parse, RangeKind};
+use super::registers::Register;
+use super::textobject::TextObject;
+use super::{Cursor, Editor, Line, Mode, Operator};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+use std::cmp::{max, min};
+use std::ops::Range;
+
+//Splits a line into the text before, inside and after the display columns
+//left..right of a block. Tabs and wide characters crossing an edge are
+//turned into spaces, so the text keeps its columns
+fn split_block(line: &Line, left: usize, right: usize) -> (String, String, String) {
+    let mut parts = [String::new(), String::new(), String::new()];
+    let part = |x| match x {
+        x if x < left => 0,
+        x if x < right => 1,
+        _ => 2,
+    };
+    let graphemes: Vec<(usize, &str)> = line.graphemes().collect();
+    for (k, &(x, grapheme)) in graphemes.iter().enumerate() {
+        let end = graphemes.get(k + 1).map_or(line.len(), |&(x, _)| x);
+        let crosses = |edge| x < edge && edge < end;
+        if crosses(left) || crosses(right) {
+            for column in x..end {
+                parts[part(column)].push(' ');
+            }
+        } else {
+            parts[part(x)].push_str(grapheme);
+        }
+    }
+    let [before, inside, after] = parts;
+    (before, inside, after)
+}
+
+fn toggle_case(text: &str) -> String {
+    text.chars()
+        .flat_map(|c| -> Box<dyn Iterator<Item = char>> {
+            if c.is_uppercase() {
+                Box::new(c.to_lowercase())
+            } else {
+                Box::new(c.to_uppercase())
+            }
+        })
+        .collect()
+}
+
+impl Editor {
+    pub(super) fn start_visual(&mut self, mode: Mode) {
+        self.visual_anchor = Cursor {
+            x: self.x(true),
+            y: self.y(),
+        };
+        self.mode = mode;
+    }
+
+    //Typing the key of the current Visual mode leaves it, and the ones of
+    //the others switch to them
+    fn switch_visual(&mut self, mode: Mode) {
+        self.mode = if self.mode == mode {
+            Mode::Normal
+        } else {
+            mode
+        };
+    }
+
+    //The ends of the selection, the first one before the second
+    fn selection(&self) -> (Cursor, Cursor) {
+        let anchor = self.visual_anchor;
+        let cursor = Cursor {
+            x: self.x(true),
+            y: self.y(),
+        };
+        if (anchor.y, anchor.x) <= (cursor.y, cursor.x) {
+            (anchor, cursor)
+        } else {
+            (cursor, anchor)
+        }
+    }
+
+    //Display columns of the block. The right one is past the character at
+    //the right edge, which may be wide. Beyond the end of its line the
+    //cursor keeps the column it wants to be in
+    fn block_columns(&self) -> (usize, usize) {
+        let cursor = Cursor {
+            x: max(self.cursor.x, self.x(true)),
+            y: self.y(),
+        };
+        let corners = [self.visual_anchor, cursor];
+        let left = min(corners[0].x, corners[1].x);
+        let right = corners
+            .iter()
+            .map(|corner| {
+                let line = self.buffer.line(corner.y);
+                match line.next_valid_index(corner.x) {
+                    Some(x) => x,
+                    None if corner.x < line.len() => line.len(),
+                    None => corner.x + 1,
+                }
+            })
+            .max()
+            .unwrap_or(left + 1);
+        (left, right)
+    }
+
+    //Display indexes of line y in the selection. The end is past the len of
+    //the line if its line break is selected too
+    pub(super) fn selected_columns(&self, y: usize) -> Option<Range<usize>> {
+        let (start, end) = self.selection();
+        if y < start.y || y > end.y {
+            return None;
+        }
+        let line = self.buffer.line(y);
+        match self.mode {
+            Mode::Visual => {
+                let from = if y == start.y { start.x } else { 0 };
+                let to = match line.next_valid_index(end.x) {
+                    Some(x) if y == end.y => x,
+                    None if y == end.y && end.x < line.len() => line.len(),
+                    _ => line.len() + 1,
+                };
+                Some(from..to)
+            }
+            Mode::VisualLine => Some(0..line.len() + 1),
+            Mode::VisualBlock => {
+                let (left, right) = self.block_columns();
+                Some(min(left, line.len())..min(right, line.len()))
+            }
+            _ => None,
+        }
+    }
+
+    //The selection as the range of a motion. A selected line break is
+    //taken as the start of the next line
+    fn selection_range(&self) -> MotionRange {
+        let (start, end) = self.selection();
+        if self.mode == Mode::VisualLine {
+            return MotionRange {
+                start,
+                end,
+                kind: RangeKind::Linewise,
+            };
+        }
+        let line = self.buffer.line(end.y);
+        let end = match line.next_valid_index(end.x) {
+            Some(x) => Cursor { x, y: end.y },
+            None if end.x < line.len() || end.y + 1 == self.buffer.len() => Cursor {
+                x: line.len(),
+                y: end.y,
+            },
+            None => Cursor { x: 0, y: end.y + 1 },
+        };
+        MotionRange {
+            start,
+            end,
+            kind: RangeKind::Charwise,
+        }
+    }
+
+    pub(super) fn match_event_visual(&mut self, event: Event) {
+        let (c, modifiers) = match event {
+            Event::Key(KeyEvent {
+                code: KeyCode::Char(c),
+                modifiers,
+            }) => (c, modifiers),
+            Event::Key(KeyEvent {
+                code: KeyCode::Esc, ..
+            }) => {
+                self.pending_keys.clear();
+                self.selected_register = None;
+                self.mode = Mode::Normal;
+                return;
+            }
+            _ => return,
+        };
+        if modifiers.contains(KeyModifiers::CONTROL) {
+            let (count, _) = split_count(&self.pending_keys);
+            self.pending_keys.clear();
+            match c {
+                'v' => self.switch_visual(Mode::VisualBlock),
+                'd' => self.scroll_half_page(true, count),
+                'u' => self.scroll_half_page(false, count),
+                'f' => self.scroll_pages(true, count.unwrap_or(1)),
+                'b' => self.scroll_pages(false, count.unwrap_or(1)),
+                _ => {}
+            }
+            return;
+        }
+        self.pending_keys.push(c);
+        let (given_count, keys) = split_count(&self.pending_keys);
+        if keys.is_empty() {
+            return;
+        }
+        if keys.starts_with('"') {
+            self.select_register();
+            return;
+        }
+        let count = given_count.unwrap_or(1);
+        match Motion::parse(keys, given_count) {
+            Parse::Complete(motion) => {
+                self.pending_keys.clear();
+                if let Some(motion) = self.resolve_char_search(motion) {
+                    self.move_cursor_to(motion, count);
+                }
+                return;
+            }
+            Parse::Pending => return,
+            Parse::Invalid => {}
+        }
+        match TextObject::parse(keys) {
+            Parse::Complete(object) => {
+                self.pending_keys.clear();
+                self.select_object(object, count);
+                return;
+            }
+            Parse::Pending => return,
+            Parse::Invalid => {}
+        }
+        let complete = keys.chars().count() == 1;
+        self.pending_keys.clear();
+        if !complete {
+            return;
+        }
+        match c {
+            'v' => self.switch_visual(Mode::Visual),
+            'V' => self.switch_visual(Mode::VisualLine),
+            'o' => {
+                let anchor = self.visual_anchor;
+                self.visual_anchor = Cursor {
+                    x: self.x(true),
+                    y: self.y(),
+                };
+                self.cursor = anchor;
+            }
+            'd' | 'x' => self.visual_operator(Operator::Delete),
+            'y' => self.visual_operator(Operator::Yank),
+            'c' | 's' => self.visual_operator(Operator::Change),
+            '>' | '<' => {
+                let (start, end) = self.selection();
+                for _ in 0..count {
+                    self.shift_lines(start.y, end.y, c == '>');
+                }
+                self.mode = Mode::Normal;
+            }
+            '~' | 'u' | 'U' => self.change_case(c),
+            'J' => {
+                let (start, end) = self.selection();
+                let end_y = min(max(end.y, start.y + 1), self.buffer.len() - 1);
+                self.join_lines(start.y, end_y);
+                self.mode = Mode::Normal;
+            }
+            ':' => {
+                let (start, end) = self.selection();
+                self.visual_lines = Some((start.y, end.y));
+                self.mode = Mode::Command;
+                self.command_buffer = String::from(":'<,'>");
+            }
+            _ => {}
+        }
+        if self.mode != Mode::Command {
+            self.selected_register = None;
+        }
+    }
+
+    //Selects the text of the object, changing to linewise selection for
+    //objects made of lines
+    fn select_object(&mut self, object: TextObject, count: usize) {
+        let range = match self.text_object_range(object, count) {
+            Some(range) => range,
+            None => return,
+        };
+        let (start, end) = (range.start, range.end);
+        if range.kind == RangeKind::Linewise {
+            self.mode = Mode::VisualLine;
+            self.visual_anchor = Cursor { x: 0, y: start.y };
+            self.cursor = Cursor { x: 0, y: end.y };
+            return;
+        }
+        self.mode = Mode::Visual;
+        self.visual_anchor = start;
+        self.cursor = match self.buffer.line(end.y).prev_valid_index(end.x) {
+            Some(x) => Cursor { x, y: end.y },
+            None if end.y > start.y => Cursor {
+                x: self.buffer.line(end.y - 1).len(),
+                y: end.y - 1,
+            },
+            None => start,
+        };
+    }
+
+    fn visual_operator(&mut self, operator: Operator) {
+        let mode = self.mode;
+        let range = self.selection_range();
+        self.mode = Mode::Normal;
+        if mode != Mode::VisualBlock {
+            self.apply_operator(operator, range);
+            return;
+        }
+        if !self.check_selected_register() {
+            return;
+        }
+        let (start, end) = self.selection();
+        let (left, right) = self.block_columns();
+        let parts: Vec<(String, String, String)> = (start.y..=end.y)
+            .map(|y| split_block(&self.buffer.line(y), left, right))
+            .collect();
+        let text: Vec<&str> = parts.iter().map(|(_, inside, _)| inside.as_str()).collect();
+        let register = Register {
+            text: text.join("\n"),
+            linewise: false,
+        };
+        self.yank(register, operator != Operator::Yank);
+        let x = min(left, self.buffer.line(start.y).len());
+        self.cursor = Cursor { x, y: start.y };
+        if operator == Operator::Yank {
+            return;
+        }
+        let lines: Vec<Line> = parts
+            .iter()
+            .map(|(before, _, after)| Line::new(&(before.to_owned() + after)))
+            .collect();
+        self.replace_lines(start.y, end.y - start.y + 1, lines);
+        if operator == Operator::Change {
+            let lines = (start.y + 1..=end.y)
+                .filter(|&y| !parts[y - start.y].1.is_empty())
+                .collect();
+            self.start_block_insert(lines, left);
+        }
+    }
+
+    //Toggles the case of the selection with ~, makes it lowercase with u and
+    //uppercase with U
+    fn change_case(&mut self, key: char) {
+        let (start, end) = self.selection();
+        let convert = |text: &str| match key {
+            'u' => text.to_lowercase(),
+            'U' => text.to_uppercase(),
+            _ => toggle_case(text),
+        };
+        for y in start.y..=end.y {
+            let columns = match self.selected_columns(y) {
+                Some(columns) => columns,
+                None => continue,
+            };
+            let line = self.buffer.line(y);
+            let changed: String = line
+                .graphemes()
+                .map(|(x, grapheme)| match columns.contains(&x) {
+                    true => convert(grapheme),
+                    false => grapheme.to_owned(),
+                })
+                .collect();
+            if changed != line.get_content() {
+                self.set_line(y, Line::new(&changed));
+            }
+        }
+        self.cursor = match self.mode {
+            Mode::VisualBlock => Cursor {
+                x: min(self.block_columns().0, self.buffer.line(start.y).len()),
+                y: start.y,
+            },
+            Mode::VisualLine => Cursor { x: 0, y: start.y },
+            _ => start,
+        };
+        self.mode = Mode::Normal;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_helpers::{editor, type_keys};
+    use super::*;
+
+    #[test]
+    fn split_blocks() {
+        let line = Line::new("a\tbc");
+        assert_eq!(
+            split_block(&line, 2, 5),
+            (String::from("a "), String::from("  b"), String::from("c"))
+        );
+    }
+
+    #[test]
+    fn delete_selection() {
+        let mut editor = editor(&["foo bar", "baz"]);
+        type_keys(&mut editor, "lvjd");
+        assert_eq!(editor.buffer, vec![Line::new("fz")]);
+        assert_eq!(editor.register('"').unwrap().text, "oo bar\nba");
+        assert!(editor.mode == Mode::Normal);
+    }
+
+    #[test]
+    fn delete_line_break() {
+        let mut editor = editor(&["a", "", "b"]);
+        type_keys(&mut editor, "jvd");
+        assert_eq!(editor.buffer, vec![Line::new("a"), Line::new("b")]);
+    }
+
+    #[test]
+    fn line_selection() {
+        let mut editor = editor(&["a", "b", "c"]);
+        type_keys(&mut editor, "Vjy");
+        assert!(editor.register('"').unwrap().linewise);
+        type_keys(&mut editor, "Vj>");
+        assert_eq!(
+            editor.buffer,
+            vec![Line::new("\ta"), Line::new("\tb"), Line::new("c")]
+        );
+        type_keys(&mut editor, "VjjJ");
+        assert_eq!(editor.buffer, vec![Line::new("\ta b c")]);
+    }
+
+    #[test]
+    fn select_objects() {
+        let mut editor = editor(&["f(a, b)"]);
+        type_keys(&mut editor, "fav");
+        assert_eq!(editor.selected_columns(0), Some(2..3));
+        type_keys(&mut editor, "i(");
+        assert_eq!(editor.selected_columns(0), Some(2..6));
+        type_keys(&mut editor, "U");
+        assert_eq!(editor.buffer, vec![Line::new("f(A, B)")]);
+    }
+
+    #[test]
+    fn block_columns_with_tabs() {
+        let mut editor = editor(&["\tab", "xxxxxyz", "1"]);
+        type_keys(&mut editor, "$<C-v>jjd");
+        assert_eq!(
+            editor.buffer,
+            vec![Line::new("\ta"), Line::new("xxxxxz"), Line::new("1")]
+        );
+        assert_eq!(editor.register('"').unwrap().text, "b\ny\n");
+        type_keys(&mut editor, "gg0<C-v>jly");
+        assert_eq!(editor.register('"').unwrap().text, "\t\nxxxx");
+    }
+
+    #[test]
+    fn change_block() {
+        let mut editor = editor(&["abc", "abc", "a"]);
+        type_keys(&mut editor, "l<C-v>ljjcX<Esc>");
+        assert_eq!(
+            editor.buffer,
+            vec![Line::new("aX"), Line::new("aX"), Line::new("a")]
+        );
+        editor.replace_lines(0, 3, vec![Line::new("abcd"), Line::new("界cd")]);
+        type_keys(&mut editor, "gg0l<C-v>jlcX<Esc>");
+        assert_eq!(editor.buffer, vec![Line::new("aXd"), Line::new(" Xd")]);
+    }
+
+    #[test]
+    fn toggle_case_of_selection() {
+        let mut editor = editor(&["aBc", "dEf"]);
+        type_keys(&mut editor, "lvj~");
+        assert_eq!(editor.buffer, vec![Line::new("abC"), Line::new("Def")]);
+        assert_eq!(editor.cursor, Cursor { x: 1, y: 0 });
+    }
+
+    #[test]
+    fn command_range() {
+        let mut editor = editor(&["a", "b", "c"]);
+        type_keys(&mut editor, "jVj:");
+        assert!(editor.mode == Mode::Command);
+        assert_eq!(editor.command_buffer, ":'<,'>");
+        assert_eq!(editor.visual_lines, Some((1, 2)));
+    }
+}
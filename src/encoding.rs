@@ -0,0 +1,160 @@
+use encoding_rs::{Encoder, EncoderResult, Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+use std::io::{self, Write};
+
+//Guesses the encoding of the contents of a file: first by its byte order
+//mark, then by whether it looks like UTF-16 (lots of zero bytes in every
+//other position) or is valid UTF-8, falling back to Latin-1
+pub fn detect(bytes: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return encoding;
+    }
+    let sample = &bytes[..bytes.len().min(4096)];
+    if sample.len().is_multiple_of(2) {
+        let zeros = |parity| {
+            sample
+                .iter()
+                .skip(parity)
+                .step_by(2)
+                .filter(|&&byte| byte == 0)
+                .count()
+        };
+        let half = sample.len() / 2;
+        if zeros(1) * 10 > half * 3 && zeros(0) * 10 < half {
+            return UTF_16LE;
+        }
+        if zeros(0) * 10 > half * 3 && zeros(1) * 10 < half {
+            return UTF_16BE;
+        }
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return UTF_8;
+    }
+    //encoding_rs treats Latin-1 as windows-1252, which maps every byte
+    WINDOWS_1252
+}
+
+//Decodes keeping the byte order mark, if any, as a U+FEFF at the start.
+//The bool is true if some bytes were invalid and got replaced
+pub fn decode(bytes: &[u8], encoding: &'static Encoding) -> (String, bool) {
+    let (text, had_errors) = encoding.decode_without_bom_handling(bytes);
+    (text.into_owned(), had_errors)
+}
+
+//Name shown to the user, like "utf-8" or "windows-1252"
+pub fn name(encoding: &'static Encoding) -> String {
+    encoding.name().to_lowercase()
+}
+
+//Encodes the text written to it into encoding before passing it to writer.
+//Fails instead of writing characters that don't exist in encoding
+pub struct EncodingWriter<W: Write> {
+    writer: W,
+    encoding: &'static Encoding,
+    encoder: Encoder,
+}
+
+impl<W: Write> EncodingWriter<W> {
+    pub fn new(writer: W, encoding: &'static Encoding) -> Self {
+        EncodingWriter {
+            writer,
+            encoding,
+            encoder: encoding.new_encoder(),
+        }
+    }
+
+    pub fn write_str(&mut self, s: &str) -> io::Result<()> {
+        //encoding_rs only decodes UTF-16, so it is encoded by hand
+        if self.encoding == UTF_16LE {
+            let bytes: Vec<u8> = s.encode_utf16().flat_map(u16::to_le_bytes).collect();
+            return self.writer.write_all(&bytes);
+        }
+        if self.encoding == UTF_16BE {
+            let bytes: Vec<u8> = s.encode_utf16().flat_map(u16::to_be_bytes).collect();
+            return self.writer.write_all(&bytes);
+        }
+        if self.encoding == UTF_8 {
+            return self.writer.write_all(s.as_bytes());
+        }
+        let max_len = self
+            .encoder
+            .max_buffer_length_from_utf8_without_replacement(s.len())
+            .unwrap_or(s.len() * 4);
+        let mut bytes = vec![0; max_len];
+        let (result, _, written) = self
+            .encoder
+            .encode_from_utf8_without_replacement(s, &mut bytes, false);
+        match result {
+            EncoderResult::InputEmpty => self.writer.write_all(&bytes[..written]),
+            EncoderResult::Unmappable(c) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Can't convert '{}' to {}", c, name(self.encoding)),
+            )),
+            EncoderResult::OutputFull => unreachable!(),
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.encoding != UTF_16LE && self.encoding != UTF_16BE && self.encoding != UTF_8 {
+            let mut bytes = vec![0; 16];
+            let (_, _, written) = self
+                .encoder
+                .encode_from_utf8_without_replacement("", &mut bytes, true);
+            self.writer.write_all(&bytes[..written])?;
+        }
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(s: &str, encoding: &'static Encoding) -> io::Result<Vec<u8>> {
+        let mut writer = EncodingWriter::new(Vec::new(), encoding);
+        writer.write_str(s)?;
+        writer.finish()
+    }
+
+    #[test]
+    fn detect_utf8() {
+        assert_eq!(detect("añ".as_bytes()), UTF_8);
+        assert_eq!(detect(b""), UTF_8);
+    }
+
+    #[test]
+    fn detect_latin1() {
+        assert_eq!(detect(b"a\xf1o\n"), WINDOWS_1252);
+        assert_eq!(
+            decode(b"a\xf1o\n", WINDOWS_1252),
+            (String::from("año\n"), false)
+        );
+    }
+
+    #[test]
+    fn detect_utf16() {
+        assert_eq!(detect(b"\xff\xfea\x00"), UTF_16LE);
+        assert_eq!(detect(b"a\x00\xf1\x00o\x00\n\x00"), UTF_16LE);
+        assert_eq!(detect(b"\x00a\x00\xf1\x00o\x00\n"), UTF_16BE);
+        assert_eq!(detect(b"a\x00b\x00"), UTF_16LE);
+    }
+
+    #[test]
+    fn decode_keeps_bom() {
+        let (text, _) = decode(b"\xfe\xff\x00a", UTF_16BE);
+        assert_eq!(text, "\u{feff}a");
+    }
+
+    #[test]
+    fn encode_round_trip() -> io::Result<()> {
+        for &encoding in &[UTF_8, UTF_16LE, UTF_16BE, WINDOWS_1252] {
+            let bytes = encode("año\n", encoding)?;
+            assert_eq!(decode(&bytes, encoding).0, "año\n");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn unmappable_character() {
+        assert!(encode("中", WINDOWS_1252).is_err());
+    }
+}
@@ -1,109 +1,253 @@
-pub const TAB_SZ: usize = 4;
+pub const DEFAULT_TAB_SZ: usize = 4;
 
+use std::cmp::{max, min};
+use std::ops::Range;
 use unicode_segmentation::GraphemeCursor;
 use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
-#[derive(Debug, PartialEq)]
+//A line of text. Indexes are always given in display columns, so a tab
+//takes up to tab_sz indexes and only the first of them is a valid one
+#[derive(Debug, Clone, PartialEq)]
 pub struct Line {
     content: String,
-    display: String,
+    tab_sz: usize,
 }
 
 impl Line {
     pub fn new(s: &str) -> Self {
+        Line::with_tab_size(s, DEFAULT_TAB_SZ)
+    }
+
+    pub fn with_tab_size(s: &str, tab_sz: usize) -> Self {
         assert!(
             !s.contains('\n'),
             "A Line can't contain a new line character('\n')"
         );
-        let mut line = Line {
+        assert!(tab_sz > 0, "A Line can't have a tab size of 0");
+        Line {
             content: s.to_owned(),
-            display: String::new(),
-        };
-        line.update_display();
-        line
+            tab_sz,
+        }
+    }
+
+    //Iterates over the graphemes of the content along with their display index
+    //and their index in the content
+    fn indexed_graphemes(&self) -> impl Iterator<Item = (usize, usize, &str)> {
+        let mut i = 0;
+        let tab_sz = self.tab_sz;
+        self.content
+            .grapheme_indices(true)
+            .map(move |(content_index, grapheme)| {
+                let index = i;
+                i += grapheme_width(grapheme, i, tab_sz);
+                (index, content_index, grapheme)
+            })
+    }
+
+    //Iterates over the graphemes of the content along with their display index
+    pub fn graphemes(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.indexed_graphemes()
+            .map(|(i, _, grapheme)| (i, grapheme))
+    }
+
+    pub fn grapheme_at(&self, index: usize) -> Option<&str> {
+        self.graphemes()
+            .find(|&(i, _)| i == index)
+            .map(|(_, grapheme)| grapheme)
     }
 
     pub fn len(&self) -> usize {
-        self.display.graphemes(true).count()
+        self.indexed_graphemes()
+            .last()
+            .map_or(0, |(i, _, grapheme)| {
+                i + grapheme_width(grapheme, i, self.tab_sz)
+            })
     }
 
     pub fn get_content(&self) -> String {
         self.content.clone()
     }
 
-    pub fn take_substr(&self, start: usize, max_len: usize) -> String {
-        let start_index = match self.display.grapheme_indices(true).skip(start).next() {
-            None => return String::from(""),
-            Some((index, _)) => index,
+    //Content between the display indexes start and end, which must be valid
+    //indexes or len
+    pub fn slice(&self, start: usize, end: usize) -> &str {
+        let len = self.len();
+        let content_index = |index| {
+            if index == len {
+                self.content.len()
+            } else {
+                self.get_content_index(index)
+            }
         };
-        match self
-            .display
-            .grapheme_indices(true)
-            .skip(start + max_len)
-            .next()
-        {
-            None => self.display[start_index..].to_owned(),
-            Some((end_index, _)) => self.display[start_index..end_index].to_owned(),
+        &self.content[content_index(start)..content_index(end)]
+    }
+
+    //Display indexes of the graphemes a range of the content touches. A range
+    //ending in the middle of a grapheme takes all of it
+    pub fn display_range(&self, range: Range<usize>) -> Range<usize> {
+        let len = self.len();
+        let (mut start, mut end) = (None, None);
+        for (i, content_index, grapheme) in self.indexed_graphemes() {
+            if start.is_none() && content_index + grapheme.len() > range.start {
+                start = Some(i);
+            }
+            if content_index >= range.end {
+                end = Some(i);
+                break;
+            }
+        }
+        start.unwrap_or(len)..end.unwrap_or(len)
+    }
+
+    //display_range of each range, going through the line once. The starts
+    //and the ends of the ranges must be in increasing order
+    pub fn display_ranges(&self, ranges: &[Range<usize>]) -> Vec<Range<usize>> {
+        let len = self.len();
+        let mut starts = self.indexed_graphemes().peekable();
+        let mut ends = self.indexed_graphemes().peekable();
+        let mut display_ranges = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            while starts
+                .next_if(|&(_, content_index, grapheme)| {
+                    content_index + grapheme.len() <= range.start
+                })
+                .is_some()
+            {}
+            while ends
+                .next_if(|&(_, content_index, _)| content_index < range.end)
+                .is_some()
+            {}
+            let start = starts.peek().map_or(len, |&(i, _, _)| i);
+            let end = ends.peek().map_or(len, |&(i, _, _)| i);
+            display_ranges.push(start..end);
+        }
+        display_ranges
+    }
+
+    //Display index ranges of the words of the line, split at the Unicode word
+    //boundaries. Like in vim, a run of punctuation is a single word. With big
+    //set, words are just runs of non-blank graphemes, what vim calls WORDs
+    pub fn words(&self, big: bool) -> Vec<Range<usize>> {
+        let indexes: Vec<(usize, usize)> = self
+            .indexed_graphemes()
+            .map(|(i, content_index, _)| (content_index, i))
+            .collect();
+        let len = self.len();
+        let display_index = |content_index| {
+            indexes
+                .iter()
+                .find(|&&(i, _)| i >= content_index)
+                .map_or(len, |&(_, i)| i)
+        };
+        let mut words: Vec<(Range<usize>, WordClass)> = Vec::new();
+        for (content_index, segment) in self.content.split_word_bound_indices() {
+            let class = match word_class(segment) {
+                WordClass::Word if big => WordClass::Other,
+                class => class,
+            };
+            if class == WordClass::Blank {
+                continue;
+            }
+            let start = display_index(content_index);
+            let end = display_index(content_index + segment.len());
+            match words.last_mut() {
+                //Other segments next to each other make up a word
+                Some((range, WordClass::Other))
+                    if range.end == start && class == WordClass::Other =>
+                {
+                    range.end = end
+                }
+                _ => words.push((start..end, class)),
+            }
         }
+        words.into_iter().map(|(range, _)| range).collect()
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.content.is_empty()
+    //Display index of the first grapheme that isn't a space or a tab, or len
+    //if there is none
+    pub fn indent(&self) -> usize {
+        self.graphemes()
+            .find(|&(_, grapheme)| grapheme != " " && grapheme != "\t")
+            .map_or(self.len(), |(i, _)| i)
     }
 
-    pub fn is_valid_index(&self, index: usize) -> bool {
-        if index >= self.len() {
-            return false;
+    //Renders the columns in start..start + max_len. Graphemes that don't fit
+    //entirely in that range are left out, except tabs, which can be split
+    pub fn take_substr(&self, start: usize, max_len: usize) -> String {
+        let end = start + max_len;
+        let mut s = String::new();
+        for (i, _, grapheme) in self.indexed_graphemes() {
+            let width = grapheme_width(grapheme, i, self.tab_sz);
+            if grapheme == "\t" {
+                let columns = max(i, start)..min(i + width, end);
+                s.extend(std::iter::repeat_n(' ', columns.len()));
+            } else if i >= start && i + width <= end {
+                s.push_str(&render(grapheme));
+            }
         }
-        let mut i = 0;
-        let mut iter = self.content.graphemes(true);
-        while i < index {
-            match iter.next() {
-                None => return false,
-                Some("\t") => i += TAB_SZ - (i % TAB_SZ),
-                Some(_) => i += 1,
+        s
+    }
+
+    //Display indexes at which each of the rows the line takes up in a screen
+    //with n_cols columns begins. A grapheme that doesn't fit in what remains
+    //of a row starts the next one, except tabs, which are split between both
+    pub fn row_starts(&self, n_cols: usize) -> Vec<usize> {
+        let n_cols = max(n_cols, 1);
+        let mut starts = vec![0];
+        for (i, _, grapheme) in self.indexed_graphemes() {
+            let width = grapheme_width(grapheme, i, self.tab_sz);
+            let mut row_start = starts[starts.len() - 1];
+            if grapheme == "\t" {
+                while i + width > row_start + n_cols {
+                    row_start += n_cols;
+                    starts.push(row_start);
+                }
+            } else if i + width > row_start + n_cols && i > row_start {
+                starts.push(i);
             }
         }
-        if i == index {
-            true
+        starts
+    }
+
+    //Column and row relative to the start of the line in which index is drawn
+    pub fn screen_pos(&self, index: usize, n_cols: usize) -> (usize, usize) {
+        let n_cols = max(n_cols, 1);
+        let starts = self.row_starts(n_cols);
+        let row = starts
+            .iter()
+            .rposition(|&start| start <= index)
+            .unwrap_or(0);
+        let col = index - starts[row];
+        if col >= n_cols {
+            (col - n_cols, row + 1)
         } else {
-            false
+            (col, row)
         }
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.content.is_empty()
+    }
+
+    pub fn is_valid_index(&self, index: usize) -> bool {
+        self.indexed_graphemes().any(|(i, _, _)| i == index)
+    }
+
     pub fn next_valid_index(&self, index: usize) -> Option<usize> {
-        let mut i = 0;
-        let mut iter = self.content.graphemes(true);
-        while i <= index {
-            match iter.next() {
-                None => return None,
-                Some("\t") => i += TAB_SZ - (i % TAB_SZ),
-                Some(_) => i += 1,
-            }
-        }
-        if let None = iter.next() {
-            None
-        } else {
-            Some(i)
-        }
+        self.indexed_graphemes()
+            .map(|(i, _, _)| i)
+            .find(|&i| i > index)
     }
 
     pub fn prev_valid_index(&self, index: usize) -> Option<usize> {
         if index == 0 {
             return None;
         }
-        let mut i = 0;
-        let mut prev_i = 0;
-        let mut iter = self.content.graphemes(true);
-        while i < index {
-            prev_i = i;
-            match iter.next() {
-                None => return None,
-                Some("\t") => i += TAB_SZ - (i % TAB_SZ),
-                Some(_) => i += 1,
-            }
-        }
-        Some(prev_i)
+        self.indexed_graphemes()
+            .map(|(i, _, _)| i)
+            .take_while(|&i| i < index)
+            .last()
     }
 
     pub fn insert(&mut self, index: usize, s: &str) {
@@ -111,29 +255,14 @@ impl Line {
             !s.contains('\n'),
             "A Line can't contain a new line character('\n')"
         );
-        let mut i = 0;
-        let mut iter = self.content.grapheme_indices(true);
-        while i < index {
-            match iter.next() {
-                None => panic!("Line: Tried to insert in a invalid index({})", index),
-                Some((_, grapheme)) => {
-                    if grapheme == "\t" {
-                        i += TAB_SZ - (i % TAB_SZ);
-                    } else {
-                        i += 1;
-                    }
-                }
-            }
-        }
-        if i != index {
-            panic!("Line: Tried to insert in a invalid index({})", index);
-        }
-        if let Some((content_index, _)) = iter.next() {
-            self.content.insert_str(content_index, s);
+        let content_index = if index == self.len() {
+            self.content.len()
+        } else if self.is_valid_index(index) {
+            self.get_content_index(index)
         } else {
-            self.content.insert_str(self.content.len(), s);
-        }
-        self.update_display();
+            panic!("Line: Tried to insert in a invalid index({})", index);
+        };
+        self.content.insert_str(content_index, s);
     }
 
     pub fn push(&mut self, s: &str) {
@@ -142,69 +271,95 @@ impl Line {
 
     pub fn split_off(&mut self, at: usize) -> Line {
         if at == self.len() {
-            return Line::new("");
+            return Line::with_tab_size("", self.tab_sz);
         }
         assert!(self.is_valid_index(at));
         let other = self.content.split_off(self.get_content_index(at));
-        self.update_display();
-        Line::new(&other)
+        Line::with_tab_size(&other, self.tab_sz)
     }
 
     pub fn remove(&mut self, index: usize) {
         assert!(self.is_valid_index(index));
         let start = self.get_content_index(index);
-        let end;
-        match GraphemeCursor::new(start, self.content.len(), true).next_boundary(&self.content, 0) {
-            Ok(Some(i)) => end = i,
-            Ok(None) => end = self.content.len(),
+        let end = match GraphemeCursor::new(start, self.content.len(), true)
+            .next_boundary(&self.content, 0)
+        {
+            Ok(Some(i)) => i,
+            Ok(None) => self.content.len(),
             _ => unreachable!(),
-        }
+        };
         self.content.replace_range(start..end, "");
-        self.update_display();
     }
 
     //Gets the equivalent index in the contente to an index in the rendered part graphemes
     //Worth notice that graphemes like á take up two bytes
     fn get_content_index(&self, index: usize) -> usize {
-        let mut i = 0;
-        let mut iter = self.content.grapheme_indices(true);
-        while i < index {
-            match iter.next() {
-                None => panic!("Line: Tried to translate an invalid index({})", index),
-                Some((_, grapheme)) => {
-                    if grapheme == "\t" {
-                        i += TAB_SZ - (i % TAB_SZ);
-                    } else {
-                        i += 1;
-                    }
-                }
-            }
+        match self.indexed_graphemes().find(|&(i, _, _)| i == index) {
+            Some((_, content_index, _)) => content_index,
+            None => panic!("Line: Tried to translate an invalid index({})", index),
         }
-        if i != index {
-            panic!("Line: Tried to translate an invalid index({})", index);
-        }
-        if let Some((content_index, _)) = iter.next() {
-            content_index
+    }
+}
+
+#[derive(PartialEq)]
+enum WordClass {
+    Blank,
+    //Letters, digits and what joins them, like the _ in foo_bar
+    Word,
+    Other,
+}
+
+fn word_class(segment: &str) -> WordClass {
+    if segment.chars().all(char::is_whitespace) {
+        WordClass::Blank
+    } else if segment.chars().any(|c| c.is_alphanumeric() || c == '_') {
+        WordClass::Word
+    } else {
+        WordClass::Other
+    }
+}
+
+fn is_control(grapheme: &str) -> bool {
+    let mut chars = grapheme.chars();
+    matches!((chars.next(), chars.next()), (Some(c), None) if c.is_control())
+}
+
+//Whether a control character is one of the C1 ones, from U+0080 to U+009F
+fn is_c1_control(grapheme: &str) -> bool {
+    grapheme.chars().next().is_some_and(|c| c >= '\u{80}')
+}
+
+//Number of display columns taken by grapheme when it starts at index.
+//Control characters are shown in caret notation (^M), or in hex (<80) if
+//they aren't ASCII, and zero-width sequences over a dotted circle, so every
+//grapheme takes at least a column
+fn grapheme_width(grapheme: &str, index: usize, tab_sz: usize) -> usize {
+    if grapheme == "\t" {
+        tab_sz - (index % tab_sz)
+    } else if is_control(grapheme) {
+        if is_c1_control(grapheme) {
+            4
         } else {
-            panic!("Line: Tried to translate an invalid index({})", index);
+            2
         }
+    } else {
+        max(grapheme.width(), 1)
     }
+}
 
-    fn update_display(&mut self) {
-        self.display.clear();
-        let mut width = 0;
-        for s in self.content.graphemes(true) {
-            width += 1;
-            if s == "\t" {
-                self.display.push(' ');
-                while width % TAB_SZ != 0 {
-                    self.display.push(' ');
-                    width += 1;
-                }
-            } else {
-                self.display.push_str(s);
-            }
+//How a grapheme other than a tab is written to the terminal
+fn render(grapheme: &str) -> String {
+    if is_control(grapheme) {
+        let c = grapheme.chars().next().unwrap_or_default();
+        if is_c1_control(grapheme) {
+            format!("<{:02x}>", c as u32)
+        } else {
+            format!("^{}", ((c as u8) ^ 0x40) as char)
         }
+    } else if grapheme.width() == 0 {
+        format!("\u{25cc}{}", grapheme)
+    } else {
+        grapheme.to_owned()
     }
 }
 
@@ -213,13 +368,13 @@ mod tests {
     #[test]
     fn new_line() {
         let line = super::Line::new("\taa\te");
-        assert_eq!(line.display, "    aa  e");
+        assert_eq!(line.take_substr(0, line.len()), "    aa  e");
     }
 
     #[test]
     fn new_line_not_ascii() {
         let line = super::Line::new("\táa\të");
-        assert_eq!(line.display, "    áa  ë");
+        assert_eq!(line.take_substr(0, line.len()), "    áa  ë");
     }
 
     #[test]
@@ -252,22 +407,94 @@ mod tests {
         assert_eq!(line.take_substr(10, 13), "")
     }
 
+    #[test]
+    fn wide_len() {
+        let line = super::Line::new("中文a");
+        assert_eq!(line.len(), 5);
+        assert!(!line.is_valid_index(1));
+        assert_eq!(line.next_valid_index(0), Some(2));
+        assert_eq!(line.prev_valid_index(4), Some(2));
+    }
+
+    #[test]
+    fn emoji_presentation() {
+        let line = super::Line::new("❤\u{fe0f}👍🏽a");
+        assert_eq!(line.len(), 5);
+    }
+
+    #[test]
+    fn zero_width_display() {
+        let line = super::Line::new("\u{301}a\r");
+        assert_eq!(line.len(), 4);
+        assert_eq!(line.take_substr(0, line.len()), "\u{25cc}\u{301}a^M");
+    }
+
+    #[test]
+    fn c1_control_display() {
+        let line = super::Line::new("\u{80}a\u{9b}");
+        assert_eq!(line.len(), 9);
+        assert_eq!(line.take_substr(0, line.len()), "<80>a<9b>");
+    }
+
+    #[test]
+    fn take_substr_wide() {
+        let line = super::Line::new("a中b");
+        assert_eq!(line.take_substr(0, 2), "a");
+        assert_eq!(line.take_substr(1, 3), "中b");
+    }
+
+    #[test]
+    fn row_starts_wide() {
+        let line = super::Line::new("ab中c");
+        assert_eq!(line.row_starts(3), vec![0, 2]);
+        assert_eq!(line.screen_pos(4, 3), (2, 1));
+    }
+
+    #[test]
+    fn row_starts_tab() {
+        let line = super::Line::new("ab\tc");
+        assert_eq!(line.row_starts(3), vec![0, 3]);
+        assert_eq!(line.screen_pos(4, 3), (1, 1));
+    }
+
+    #[test]
+    fn screen_pos_after_full_row() {
+        let line = super::Line::new("abc");
+        assert_eq!(line.row_starts(3), vec![0]);
+        assert_eq!(line.screen_pos(3, 3), (0, 1));
+    }
+
+    #[test]
+    fn custom_tab_size() {
+        let line = super::Line::with_tab_size("\táñ\të", 8);
+        assert_eq!(line.len(), 17);
+        assert_eq!(line.take_substr(0, line.len()), "        áñ      ë");
+        assert_eq!(line.next_valid_index(0), Some(8));
+    }
+
+    #[test]
+    fn grapheme_at() {
+        let line = super::Line::new("\táñ\të");
+        assert_eq!(line.grapheme_at(5), Some("ñ"));
+        assert_eq!(line.grapheme_at(1), None);
+    }
+
     #[test]
     fn valid_index() {
         let line = super::Line::new("\táñ\të");
-        assert_eq!(line.is_valid_index(8), true);
+        assert!(line.is_valid_index(8));
     }
 
     #[test]
     fn invalid_index() {
         let line = super::Line::new("\táñ\të");
-        assert_eq!(line.is_valid_index(7), false);
+        assert!(!line.is_valid_index(7));
     }
 
     #[test]
     fn invalid_index_beyond_len() {
         let line = super::Line::new("\táñ\të");
-        assert_eq!(line.is_valid_index(9), false);
+        assert!(!line.is_valid_index(9));
     }
 
     #[test]
@@ -379,4 +606,42 @@ mod tests {
         let line = super::Line::new("\táñ\të");
         line.get_content_index(9);
     }
+
+    #[test]
+    fn slice() {
+        let line = super::Line::new("\táñ\të");
+        assert_eq!(line.slice(4, 8), "áñ\t");
+        assert_eq!(line.slice(8, 9), "ë");
+        assert_eq!(line.slice(9, 9), "");
+    }
+
+    #[test]
+    fn indent() {
+        assert_eq!(super::Line::new("\t  a").indent(), 6);
+        assert_eq!(super::Line::new("a").indent(), 0);
+        assert_eq!(super::Line::new("  ").indent(), 2);
+    }
+
+    #[test]
+    fn words() {
+        let line = super::Line::new("\taño_1, can't... más");
+        assert_eq!(line.words(false), vec![4..9, 9..10, 11..16, 16..19, 20..23]);
+        assert_eq!(line.words(true), vec![4..10, 11..19, 20..23]);
+        assert!(super::Line::new("  ").words(false).is_empty());
+    }
+
+    #[test]
+    fn display_range() {
+        //The tab takes 4 columns, ñ 2 bytes and 界 2 columns
+        let line = super::Line::new("\tañ界");
+        assert_eq!(line.display_range(1..2), 4..5);
+        assert_eq!(line.display_range(1..3), 4..6);
+        assert_eq!(line.display_range(3..4), 5..6);
+        assert_eq!(line.display_range(4..7), 6..8);
+        assert_eq!(line.display_range(9..9), 8..8);
+        assert_eq!(
+            line.display_ranges(&[1..2, 1..3, 3..4, 4..7, 9..9]),
+            vec![4..5, 4..6, 5..6, 6..8, 8..8]
+        );
+    }
 }
@@ -1,8 +1,11 @@
 use crossterm::Result;
+mod buffer;
 mod editor;
+mod encoding;
 use editor::Editor;
 mod line;
 mod modes;
+mod options;
 
 fn main() -> Result<()> {
     Editor::init()
@@ -5,6 +5,22 @@ pub enum Mode {
     Normal,
     Insert,
     Command,
+    //Asking what to do with the swap file found when opening a file
+    Recovery,
+    //Waiting for the motion an operator acts on, after typing d, c, y...
+    OperatorPending(Operator),
+    //Selecting text characterwise, linewise or as a block of columns
+    Visual,
+    VisualLine,
+    VisualBlock,
+    //Asking whether to replace each match of a :s with the c flag
+    Confirm,
+}
+
+impl Mode {
+    pub fn is_visual(self) -> bool {
+        matches!(self, Mode::Visual | Mode::VisualLine | Mode::VisualBlock)
+    }
 }
 
 impl Display for Mode {
@@ -13,6 +29,45 @@ impl Display for Mode {
             Mode::Normal => write!(f, "Normal"),
             Mode::Insert => write!(f, "Insert"),
             Mode::Command => write!(f, "Command"),
+            Mode::Recovery => write!(f, "Recovery"),
+            Mode::OperatorPending(_) => write!(f, "Operator-pending"),
+            Mode::Visual => write!(f, "Visual"),
+            Mode::VisualLine => write!(f, "Visual Line"),
+            Mode::VisualBlock => write!(f, "Visual Block"),
+            Mode::Confirm => write!(f, "Confirm"),
+        }
+    }
+}
+
+//Commands that act on the text a motion moves over
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+    ShiftRight,
+    ShiftLeft,
+}
+
+impl Operator {
+    pub fn from_key(key: char) -> Option<Operator> {
+        match key {
+            'd' => Some(Operator::Delete),
+            'c' => Some(Operator::Change),
+            'y' => Some(Operator::Yank),
+            '>' => Some(Operator::ShiftRight),
+            '<' => Some(Operator::ShiftLeft),
+            _ => None,
+        }
+    }
+
+    pub fn key(self) -> char {
+        match self {
+            Operator::Delete => 'd',
+            Operator::Change => 'c',
+            Operator::Yank => 'y',
+            Operator::ShiftRight => '>',
+            Operator::ShiftLeft => '<',
         }
     }
 }
@@ -0,0 +1,253 @@
+use super::encoding;
+use super::line::DEFAULT_TAB_SZ;
+use encoding_rs::{Encoding, UTF_8};
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+//Line ending used when writing the file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileFormat {
+    Unix,
+    Dos,
+}
+
+impl FileFormat {
+    pub fn line_ending(self) -> &'static str {
+        match self {
+            FileFormat::Unix => "\n",
+            FileFormat::Dos => "\r\n",
+        }
+    }
+}
+
+impl Display for FileFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FileFormat::Unix => write!(f, "unix"),
+            FileFormat::Dos => write!(f, "dos"),
+        }
+    }
+}
+
+impl FromStr for FileFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unix" => Ok(FileFormat::Unix),
+            "dos" => Ok(FileFormat::Dos),
+            _ => Err(format!("Invalid argument: fileformat={}", s)),
+        }
+    }
+}
+
+//Settings of a buffer, changed with :set or in the config file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub tabstop: usize,
+    //0 means using tabstop
+    pub shiftwidth: usize,
+    pub expandtab: bool,
+    pub fileformat: FileFormat,
+    pub fileencoding: &'static Encoding,
+    //Whether the file starts with a byte order mark
+    pub bomb: bool,
+    //Whether the last line of the file ends with a line ending
+    pub endofline: bool,
+    //Whether writing the file requires :w!
+    pub readonly: bool,
+    //Whether searches ignore case, unless the pattern has uppercase letters
+    //and smartcase is set
+    pub ignorecase: bool,
+    pub smartcase: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            tabstop: DEFAULT_TAB_SZ,
+            shiftwidth: 0,
+            expandtab: false,
+            fileformat: FileFormat::Unix,
+            fileencoding: UTF_8,
+            bomb: false,
+            endofline: true,
+            readonly: false,
+            ignorecase: false,
+            smartcase: false,
+        }
+    }
+}
+
+impl Options {
+    //Columns of an indentation level, also used as the width of soft tabs
+    pub fn shiftwidth(&self) -> usize {
+        if self.shiftwidth == 0 {
+            self.tabstop
+        } else {
+            self.shiftwidth
+        }
+    }
+
+    //The full name of a boolean option and its value
+    fn bool_value(&self, name: &str) -> Option<(&'static str, bool)> {
+        match name {
+            "expandtab" | "et" => Some(("expandtab", self.expandtab)),
+            "bomb" => Some(("bomb", self.bomb)),
+            "endofline" | "eol" => Some(("endofline", self.endofline)),
+            "readonly" | "ro" => Some(("readonly", self.readonly)),
+            "ignorecase" | "ic" => Some(("ignorecase", self.ignorecase)),
+            "smartcase" | "scs" => Some(("smartcase", self.smartcase)),
+            _ => None,
+        }
+    }
+
+    fn bool_option(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "expandtab" | "et" => Some(&mut self.expandtab),
+            "bomb" => Some(&mut self.bomb),
+            "endofline" | "eol" => Some(&mut self.endofline),
+            "readonly" | "ro" => Some(&mut self.readonly),
+            "ignorecase" | "ic" => Some(&mut self.ignorecase),
+            "smartcase" | "scs" => Some(&mut self.smartcase),
+            _ => None,
+        }
+    }
+
+    //Applies one argument of :set, like "ts=8", "expandtab", "noet" or "sw?".
+    //Returns the text to show to the user, if any
+    pub fn set(&mut self, arg: &str) -> Result<Option<String>, String> {
+        if let Some(name) = arg.strip_suffix('?') {
+            return self.show(name).map(Some);
+        }
+        if let Some(i) = arg.find('=') {
+            let (name, value) = (&arg[..i], &arg[i + 1..]);
+            let number = || {
+                value
+                    .parse::<usize>()
+                    .map_err(|_| format!("Invalid argument: {}", arg))
+            };
+            match name {
+                "tabstop" | "ts" => match number()? {
+                    0 => return Err(format!("Argument must be positive: {}", arg)),
+                    n => self.tabstop = n,
+                },
+                "shiftwidth" | "sw" => self.shiftwidth = number()?,
+                "fileformat" | "ff" => self.fileformat = value.parse()?,
+                "fileencoding" | "fenc" => {
+                    self.fileencoding = Encoding::for_label(value.as_bytes())
+                        .ok_or_else(|| format!("Invalid argument: {}", arg))?
+                }
+                _ => return Err(format!("Unknown option: {}", name)),
+            }
+            return Ok(None);
+        }
+        if let Some(option) = self.bool_option(arg) {
+            *option = true;
+            return Ok(None);
+        }
+        if let Some(option) = arg
+            .strip_prefix("no")
+            .and_then(|name| self.bool_option(name))
+        {
+            *option = false;
+            return Ok(None);
+        }
+        self.show(arg).map(Some)
+    }
+
+    fn show(&self, name: &str) -> Result<String, String> {
+        match name {
+            "tabstop" | "ts" => Ok(format!("tabstop={}", self.tabstop)),
+            "shiftwidth" | "sw" => Ok(format!("shiftwidth={}", self.shiftwidth)),
+            "fileformat" | "ff" => Ok(format!("fileformat={}", self.fileformat)),
+            "fileencoding" | "fenc" => Ok(format!(
+                "fileencoding={}",
+                encoding::name(self.fileencoding)
+            )),
+            "" | "all" => Ok([
+                "tabstop",
+                "shiftwidth",
+                "expandtab",
+                "fileformat",
+                "fileencoding",
+                "bomb",
+                "endofline",
+                "readonly",
+                "ignorecase",
+                "smartcase",
+            ]
+            .iter()
+            .map(|name| self.show(name))
+            .collect::<Result<Vec<String>, String>>()?
+            .join(" ")),
+            name => match self.bool_value(name) {
+                Some((name, true)) => Ok(String::from(name)),
+                Some((name, false)) => Ok(format!("no{}", name)),
+                None => Err(format!("Unknown option: {}", name)),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_values() {
+        let mut options = Options::default();
+        assert_eq!(options.set("ts=8"), Ok(None));
+        assert_eq!(options.set("et"), Ok(None));
+        assert_eq!(options.tabstop, 8);
+        assert!(options.expandtab);
+        assert_eq!(options.shiftwidth(), 8);
+        assert_eq!(options.set("shiftwidth=2"), Ok(None));
+        assert_eq!(options.shiftwidth(), 2);
+    }
+
+    #[test]
+    fn show_values() {
+        let mut options = Options::default();
+        assert_eq!(options.set("ts?"), Ok(Some(String::from("tabstop=4"))));
+        assert_eq!(options.set("et"), Ok(None));
+        assert_eq!(options.set("expandtab"), Ok(None));
+        assert_eq!(options.set("noet"), Ok(None));
+        assert_eq!(options.set("et?"), Ok(Some(String::from("noexpandtab"))));
+    }
+
+    #[test]
+    fn set_fileformat() {
+        let mut options = Options::default();
+        assert_eq!(options.set("ff=dos"), Ok(None));
+        assert_eq!(options.fileformat, FileFormat::Dos);
+        assert_eq!(options.set("noeol"), Ok(None));
+        assert!(!options.endofline);
+        assert!(options.set("ff=mac").is_err());
+        assert_eq!(
+            options.set("fileformat?"),
+            Ok(Some(String::from("fileformat=dos")))
+        );
+    }
+
+    #[test]
+    fn set_fileencoding() {
+        let mut options = Options::default();
+        assert_eq!(options.set("fenc=latin1"), Ok(None));
+        assert_eq!(
+            options.set("fenc?"),
+            Ok(Some(String::from("fileencoding=windows-1252")))
+        );
+        assert!(options.set("fenc=foo").is_err());
+    }
+
+    #[test]
+    fn invalid_values() {
+        let mut options = Options::default();
+        assert!(options.set("ts=0").is_err());
+        assert!(options.set("ts=a").is_err());
+        assert!(options.set("foo=1").is_err());
+        assert!(options.set("nofoo").is_err());
+        assert_eq!(options.tabstop, 4);
+    }
+}